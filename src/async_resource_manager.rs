@@ -1,5 +1,20 @@
 use bytes::Bytes;
-use std::{collections::HashMap, rc::Rc, time::Duration};
+use futures::{
+    future::{AbortRegistration, Abortable},
+    stream::{FuturesUnordered, StreamExt},
+};
+use reqwest::{
+    header::{IF_MODIFIED_SINCE, IF_NONE_MATCH},
+    Client, StatusCode, Url,
+};
+use std::{
+    borrow::Cow,
+    fmt,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use unicode_bidi::BidiInfo;
 
 use sdl2::{
     image::LoadTexture,
@@ -8,99 +23,461 @@ use sdl2::{
     ttf::Font,
     video::WindowContext,
 };
-use tokio::sync::{
-    mpsc::{self, error::TrySendError},
-    oneshot::{self, error::TryRecvError},
-};
+
+use crate::content_source::concurrency_limit;
+use crate::disk_cache::{CachePolicy, CachedImage, DiskCache};
+use crate::download_queue::{DownloadQueue, Priority};
+use crate::failed_images::{FailedImages, FailureState};
+use crate::pending_downloads::PendingDownloads;
+use crate::texture_cache::{TextureBudget, TextureCache};
+use tokio::sync::{mpsc, oneshot};
 
 #[derive(Debug)]
 pub struct DownloadResponse {
     pub bytes: Bytes,
 }
 
-// Provides a url for the download loop to download,
-// and a channel to send the downloaded bytes, or None if there's an error.
-// Future: Probably better to use a result here in a real application,
-// but for this POC we don't care about the error specifics
+/// Everything that can go wrong while fetching an image
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadError {
+    /// The url can't be requested at all
+    InvalidUrl { url: String, message: String },
+    /// DNS lookup or connecting to the server failed
+    Connect { url: String, message: String },
+    /// The server didn't answer within the request timeout
+    Timeout { url: String },
+    /// The server responded, but not with a success status
+    Status { url: String, status: StatusCode },
+    /// The connection failed while reading the response body
+    Body { url: String, message: String },
+    /// A local file could not be read
+    Io { url: String, message: String },
+    /// The bytes are not an image SDL can decode
+    Decode { url: String, message: String },
+}
+
+impl DownloadError {
+    fn from_reqwest(url: &str, error: reqwest::Error) -> Self {
+        let url = url.to_string();
+        let message = error.to_string();
+        if error.is_timeout() {
+            DownloadError::Timeout { url }
+        } else if error.is_builder() {
+            DownloadError::InvalidUrl { url, message }
+        } else if error.is_body() || error.is_decode() {
+            DownloadError::Body { url, message }
+        } else {
+            DownloadError::Connect { url, message }
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            DownloadError::InvalidUrl { url, .. }
+            | DownloadError::Connect { url, .. }
+            | DownloadError::Timeout { url }
+            | DownloadError::Status { url, .. }
+            | DownloadError::Body { url, .. }
+            | DownloadError::Io { url, .. }
+            | DownloadError::Decode { url, .. } => url,
+        }
+    }
+
+    /// Whether trying again would give the same result.
+    /// Client errors are permanent, except for timeouts and rate limiting.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            DownloadError::InvalidUrl { .. }
+            | DownloadError::Io { .. }
+            | DownloadError::Decode { .. } => true,
+            DownloadError::Status { status, .. } => {
+                status.is_client_error()
+                    && *status != StatusCode::REQUEST_TIMEOUT
+                    && *status != StatusCode::TOO_MANY_REQUESTS
+            }
+            DownloadError::Connect { .. }
+            | DownloadError::Timeout { .. }
+            | DownloadError::Body { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::InvalidUrl { url, message } => {
+                write!(f, "invalid url {}: {}", url, message)
+            }
+            DownloadError::Connect { url, message } => {
+                write!(f, "could not connect for {}: {}", url, message)
+            }
+            DownloadError::Timeout { url } => write!(f, "{} timed out", url),
+            DownloadError::Status { url, status } => write!(f, "{} returned {}", url, status),
+            DownloadError::Body { url, message } | DownloadError::Io { url, message } => {
+                write!(f, "could not read {}: {}", url, message)
+            }
+            DownloadError::Decode { url, message } => {
+                write!(f, "could not decode {}: {}", url, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+pub type DownloadResult = Result<DownloadResponse, DownloadError>;
+
+/// How the download loop retries downloads that failed for reasons that may go away
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, 0 to never retry
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every retry after that
+    pub base_delay: Duration,
+    /// Longest wait before a retry
+    pub max_delay: Duration,
+    /// Once every retry failed, wait before an image is requested again the next time it's drawn
+    pub retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `retry`, counting from 0.
+    /// Half of the backoff is random, so downloads that failed together don't all retry together.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        backoff / 2 + (backoff / 2).mul_f64(rand::random::<f64>())
+    }
+}
+
+// Provides a url for the download loop to download, how urgently it's needed,
+// and a channel to send the downloaded bytes, or the reason they couldn't be downloaded
+// If there's an abort registration, aborting its handle stops the download wherever it is,
+// and nothing is sent back
 pub struct DownloadRequest {
     pub url: String,
-    pub response_channel: oneshot::Sender<Option<DownloadResponse>>,
+    pub priority: Priority,
+    pub response_channel: oneshot::Sender<DownloadResult>,
+    pub abort: Option<AbortRegistration>,
+}
+
+/// What the download loop can be asked to do
+pub enum DownloadMessage {
+    Fetch(DownloadRequest),
+    /// Moves a queued request to another priority, no effect once it has started
+    SetPriority {
+        url: String,
+        priority: Priority,
+    },
 }
 
 // Meant to be spawned off as a "parallel" async task
-// Infinitely loops reading and handling download requests from the receiver
-// If bounded is true, will handle requests one at a time, otherwise spawns a new task for each request.
+// Loops reading download requests from the receiver until it closes and every request is handled
+// Requests are queued by priority, and up to concurrency of them are downloaded at the same time,
+// each in its own task.
 // If slow is true, adds a 2 second pause for each request to (poorly) simulate a slow connection
-pub async fn download_loop(mut rx: mpsc::Receiver<DownloadRequest>, slow: bool, bounded: bool) {
-    while let Some(req) = rx.recv().await {
-        if bounded {
-            handle_request(req, slow).await;
-        } else {
-            tokio::spawn(handle_request(req, slow));
+// The client is cheap to clone, and clones share a connection pool
+// If there's a disk cache, images are read from and saved to it
+pub async fn download_loop(
+    mut rx: mpsc::Receiver<DownloadMessage>,
+    client: Client,
+    cache: Option<Arc<DiskCache>>,
+    retry: RetryPolicy,
+    slow: bool,
+    concurrency: usize,
+) {
+    let concurrency = concurrency_limit(concurrency);
+    let mut queue = DownloadQueue::new();
+    let mut running = FuturesUnordered::new();
+    let mut closed = false;
+    loop {
+        while running.len() < concurrency {
+            match queue.pop() {
+                Some(req) => running.push(tokio::spawn(handle_request(
+                    req,
+                    client.clone(),
+                    cache.clone(),
+                    retry,
+                    slow,
+                ))),
+                None => break,
+            }
+        }
+        if closed && running.is_empty() {
+            break;
+        }
+        tokio::select! {
+            msg = rx.recv(), if !closed => match msg {
+                Some(DownloadMessage::Fetch(req)) => queue.push(req),
+                Some(DownloadMessage::SetPriority { url, priority }) => {
+                    queue.set_priority(&url, priority)
+                }
+                // Whatever is queued is still downloaded
+                None => closed = true,
+            },
+            Some(_) = running.next(), if !running.is_empty() => (),
         }
     }
 }
 
-async fn handle_request(req: DownloadRequest, slow: bool) {
-    let get_resp = reqwest::get(req.url).await;
-    let download_resp = match get_resp {
-        Ok(data) => match data.bytes().await {
-            Ok(bytes) => Some(DownloadResponse { bytes }),
-            // For this POC, just print errors we encounter
-            // Futre improvement might be to change response to a result instead of Option
-            // and let requester handle the error appropriately
-            Err(e) => {
-                println!("{}", e);
-                None
-            }
+async fn handle_request(
+    req: DownloadRequest,
+    client: Client,
+    cache: Option<Arc<DiskCache>>,
+    retry: RetryPolicy,
+    slow: bool,
+) {
+    let work = fetch_with_retries(&req.url, client, cache, retry, slow);
+    let download_resp = match req.abort {
+        Some(registration) => match Abortable::new(work, registration).await {
+            Ok(download_resp) => download_resp,
+            // Nobody is waiting for the image any more
+            Err(_) => return,
         },
-        Err(e) => {
-            println!("{}", e);
-            None
+        None => work.await,
+    };
+    // The receiver is dropped when the image is no longer wanted
+    let _ = req.response_channel.send(download_resp);
+}
+
+async fn fetch_with_retries(
+    url: &str,
+    client: Client,
+    cache: Option<Arc<DiskCache>>,
+    retry: RetryPolicy,
+    slow: bool,
+) -> DownloadResult {
+    let mut retries = 0;
+    let download_resp = loop {
+        match fetch(&client, cache.as_ref(), url).await {
+            Err(e) if !e.is_permanent() && retries < retry.max_retries => {
+                let delay = retry.delay(retries);
+                println!("{}, retrying in {:?}", e, delay);
+                tokio::time::sleep(delay).await;
+                retries += 1;
+            }
+            result => break result,
         }
     };
     if slow {
         tokio::time::sleep(Duration::from_millis(2000)).await;
     }
-    if req.response_channel.send(download_resp).is_err() {
-        println!("Response channel closed unexpectedly");
+    download_resp
+}
+
+async fn fetch(client: &Client, cache: Option<&Arc<DiskCache>>, url: &str) -> DownloadResult {
+    // Recorded snapshots point tiles at local files
+    if url.starts_with("file://") {
+        read_file(url).await
+    } else if let Some(cache) = cache {
+        cached_download(client, cache, url).await
+    } else {
+        download(client, url).await
+    }
+}
+
+async fn download(client: &Client, url: &str) -> DownloadResult {
+    let reqwest_error = |e| DownloadError::from_reqwest(url, e);
+    let resp = client.get(url).send().await.map_err(reqwest_error)?;
+    if !resp.status().is_success() {
+        return Err(DownloadError::Status {
+            url: url.to_string(),
+            status: resp.status(),
+        });
+    }
+    let bytes = resp.bytes().await.map_err(reqwest_error)?;
+    Ok(DownloadResponse { bytes })
+}
+
+// Cached images are served straight from disk. Stale ones are revalidated with a conditional GET
+// in the background, so a repeat launch paints without waiting on the server, and a changed image
+// shows up the next time it's read. Only no-cache images wait for their revalidation.
+async fn cached_download(client: &Client, cache: &Arc<DiskCache>, url: &str) -> DownloadResult {
+    match cache.get(url).await {
+        Some(cached) if cached.fresh => Ok(DownloadResponse {
+            bytes: cached.bytes,
+        }),
+        Some(cached) if !cached.policy.no_cache => {
+            if cache.start_revalidation(url) {
+                let (client, cache, url) = (client.clone(), cache.clone(), url.to_string());
+                let stale = cached.clone();
+                tokio::spawn(async move {
+                    let _ = conditional_download(&client, &cache, &url, Some(stale)).await;
+                });
+            }
+            Ok(DownloadResponse {
+                bytes: cached.bytes,
+            })
+        }
+        cached => conditional_download(client, cache, url, cached).await,
     }
 }
 
+// Downloads the image unless the server says the cached copy is still current.
+// If the server can't be reached, a stale image is better than none.
+async fn conditional_download(
+    client: &Client,
+    cache: &DiskCache,
+    url: &str,
+    cached: Option<CachedImage>,
+) -> DownloadResult {
+    let mut request = client.get(url);
+    if let Some(cached) = &cached {
+        if let Some(etag) = &cached.policy.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.policy.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let stale = cached.map(|cached| DownloadResponse {
+        bytes: cached.bytes,
+    });
+
+    let resp = match request.send().await {
+        Ok(resp) => resp,
+        Err(e) => return or_stale(stale, DownloadError::from_reqwest(url, e)),
+    };
+    let status = resp.status();
+    match stale {
+        Some(stale) if status == StatusCode::NOT_MODIFIED => {
+            cache
+                .refresh(url, CachePolicy::from_headers(resp.headers()))
+                .await;
+            Ok(stale)
+        }
+        stale if !status.is_success() => or_stale(
+            stale,
+            DownloadError::Status {
+                url: url.to_string(),
+                status,
+            },
+        ),
+        stale => {
+            let policy = CachePolicy::from_headers(resp.headers());
+            match resp.bytes().await {
+                Ok(bytes) => {
+                    cache.put(url, &bytes, policy).await;
+                    Ok(DownloadResponse { bytes })
+                }
+                Err(e) => or_stale(stale, DownloadError::from_reqwest(url, e)),
+            }
+        }
+    }
+}
+
+fn or_stale(stale: Option<DownloadResponse>, error: DownloadError) -> DownloadResult {
+    match stale {
+        Some(stale) => {
+            println!("{}, using cached copy", error);
+            Ok(stale)
+        }
+        None => Err(error),
+    }
+}
+
+async fn read_file(url: &str) -> DownloadResult {
+    let path = match Url::parse(url).map(|parsed| parsed.to_file_path()) {
+        Ok(Ok(path)) => path,
+        _ => {
+            return Err(DownloadError::InvalidUrl {
+                url: url.to_string(),
+                message: "not a local file".to_string(),
+            })
+        }
+    };
+    match tokio::fs::read(&path).await {
+        Ok(bytes) => Ok(DownloadResponse {
+            bytes: Bytes::from(bytes),
+        }),
+        Err(e) => Err(DownloadError::Io {
+            url: url.to_string(),
+            message: e.to_string(),
+        }),
+    }
+}
+
+// SDL_ttf lays glyphs out left to right in the order they're stored, so text containing
+// right-to-left scripts like Hebrew or Arabic has to be put into display order first.
+// Left-to-right text is returned untouched.
+fn visual_order(text: &str) -> Cow<'_, str> {
+    let bidi_info = BidiInfo::new(text, None);
+    if !bidi_info.has_rtl() {
+        return Cow::Borrowed(text);
+    }
+    let lines: Vec<Cow<'_, str>> = bidi_info
+        .paragraphs
+        .iter()
+        .map(|para| bidi_info.reorder_line(para, para.range.clone()))
+        .collect();
+    Cow::Owned(lines.concat())
+}
+
+/// Where an image is on its way to the screen
+pub enum ImageState<'a> {
+    /// Requested, or about to be, and not ready yet
+    Loading,
+    Loaded(Rc<Texture<'a>>),
+    /// Failed for a reason that may go away, and will be requested again after a while
+    Retrying(DownloadError),
+    /// Will never load
+    Failed(DownloadError),
+}
+
 pub struct AsyncResourceManager<'l> {
     // Textures are only valid while TextureCreator lives
     // Lifetime of this struct and all textures should match texture creator
     texture_creator: &'l TextureCreator<WindowContext>,
-    cache: HashMap<String, Rc<Texture<'l>>>,
-    font_cache: HashMap<String, (Rc<Texture<'l>>, (u32, u32))>,
-    in_progress: HashMap<String, oneshot::Receiver<Option<DownloadResponse>>>,
+    // Each is kept within the texture budget separately
+    cache: TextureCache<Rc<Texture<'l>>>,
+    font_cache: TextureCache<(Rc<Texture<'l>>, (u32, u32))>,
+    downloads: PendingDownloads,
+    // Images that failed, so they aren't requested again every frame
+    failed: FailedImages,
     default_font: Font<'l, 'l>,
-    tx: mpsc::Sender<DownloadRequest>,
 }
 
 impl<'l> AsyncResourceManager<'l> {
     pub fn new(
         texture_creator: &'l TextureCreator<WindowContext>,
-        tx: mpsc::Sender<DownloadRequest>,
+        tx: mpsc::Sender<DownloadMessage>,
         font: Font<'l, 'l>,
+        budget: TextureBudget,
+        retry: RetryPolicy,
+        cancel_after: Duration,
     ) -> Self {
         Self {
             texture_creator,
-            cache: HashMap::new(),
-            font_cache: HashMap::new(),
-            in_progress: HashMap::new(),
-            tx,
+            cache: TextureCache::new(budget),
+            font_cache: TextureCache::new(budget),
+            downloads: PendingDownloads::new(tx, cancel_after),
+            failed: FailedImages::new(retry.retry_after),
             default_font: font,
         }
     }
 
-    pub fn get_text_texture_and_size(&mut self, text: &str) -> (Rc<Texture>, (u32, u32)) {
-        if self.font_cache.contains_key(text) {
-            self.font_cache.get(text).unwrap().clone()
+    pub fn get_text_texture_and_size(&mut self, text: &str) -> (Rc<Texture<'_>>, (u32, u32)) {
+        if let Some(cached) = self.font_cache.get(text) {
+            cached
         } else {
+            // Cached by the original text, only the rendered copy is reordered
+            let display_text = visual_order(text);
             let surface = self
                 .default_font
-                .render(text)
+                .render(&display_text)
                 .blended(Color::RGBA(255, 255, 255, 255))
                 .unwrap();
             let texture = Rc::new(
@@ -108,69 +485,227 @@ impl<'l> AsyncResourceManager<'l> {
                     .create_texture_from_surface(&surface)
                     .unwrap(),
             );
-            let font_size = self.default_font.size_of(text).unwrap();
-            self.font_cache
-                .insert(text.to_string(), (texture.clone(), font_size));
+            let font_size = self.default_font.size_of(&display_text).unwrap();
+            self.font_cache.insert(
+                text.to_string(),
+                (texture.clone(), font_size),
+                texture_bytes(&texture),
+            );
             (texture, font_size)
         }
     }
 
-    pub fn get_image_from_url(&mut self, url: &str) -> Option<Rc<Texture>> {
-        if self.cache.contains_key(url) {
-            // Cloning an Rc is relatively cheap because we're just cloning the pointer.
-            // We do NOT clone the texture it points to, which could be expensive.
-            // Using an Rc instead of a reference gives us a bit more flexibility with the borrow checker
-            self.cache.get(url).cloned()
-        } else {
-            // If a cached copy doesn't exist, and if there's not an in-progress request for this url,
-            // issue a request to the download loop to download this image
-            if !self.in_progress.contains_key(url) {
-                let (resp_tx, resp_rx) = oneshot::channel();
-                let msg = DownloadRequest {
-                    url: url.to_string(),
-                    response_channel: resp_tx,
-                };
-                match self.tx.try_send(msg) {
-                    Ok(_) => {
-                        self.in_progress.insert(url.to_string(), resp_rx);
-                    }
-                    // If other side is closed, we cannot recover
-                    Err(TrySendError::Closed(_)) => panic!("Downloader closed unexpectedly"),
-                    // All other errors can be ignored for this POC
-                    Err(_) => (),
-                }
-            }
-            None
+    /// Returns the image if it's ready, otherwise requests it at priority.
+    /// Anything still loading has to be asked for every frame to keep its place in the queue.
+    pub fn get_image_from_url(&mut self, url: &str, priority: Priority) -> ImageState<'_> {
+        // Cloning an Rc is relatively cheap because we're just cloning the pointer.
+        // We do NOT clone the texture it points to, which could be expensive.
+        // Using an Rc instead of a reference gives us a bit more flexibility with the borrow checker
+        if let Some(texture) = self.cache.get(url) {
+            return ImageState::Loaded(texture);
         }
+        if url.is_empty() {
+            return ImageState::Failed(DownloadError::InvalidUrl {
+                url: String::new(),
+                message: "tile has no image".to_string(),
+            });
+        }
+        match self.failed.state(url, Instant::now()) {
+            Some(FailureState::Failed(error)) => return ImageState::Failed(error),
+            Some(FailureState::Retrying(error)) => return ImageState::Retrying(error),
+            None => (),
+        }
+        // If a cached copy doesn't exist, and if there's not an in-progress request for this url,
+        // issue a request to the download loop to download this image
+        self.downloads.want(url, priority, Instant::now());
+        ImageState::Loading
+    }
+
+    /// Requests an image ahead of time, so it's ready by the time it scrolls into view.
+    /// A loaded image is kept like one that was drawn.
+    pub fn prefetch_image(&mut self, url: &str) {
+        self.get_image_from_url(url, Priority::Prefetch);
     }
 
     pub fn process_pending(&mut self) {
-        // Can't easily modify a map while iterating through it,
-        // so maintain list of what needs to be removed after the loop
-        let mut remove_set = Vec::new();
-        for (key, rx) in self.in_progress.iter_mut() {
-            // try_recv instantly returns with either a valid value, or an error
-            // There's no blocking and no need to await or yield control of the thread
-            match rx.try_recv() {
+        let mut failures = Vec::new();
+        for (url, result) in self.downloads.finished() {
+            match result {
                 // Take image, convert to texture and add to cache
-                Ok(val) => {
-                    remove_set.push(key.clone());
-                    if let Some(resp) = val {
-                        let texture = self.texture_creator.load_texture_bytes(&resp.bytes);
-                        if let Ok(texture) = texture {
-                            self.cache.insert(key.clone(), Rc::new(texture));
-                        }
+                Ok(resp) => match self.texture_creator.load_texture_bytes(&resp.bytes) {
+                    Ok(texture) => {
+                        let bytes = texture_bytes(&texture);
+                        self.cache.insert(url, Rc::new(texture), bytes);
                     }
-                }
-                // If other side closed unexpectedly, we can remove it and try again later
-                Err(TryRecvError::Closed) => remove_set.push(key.clone()),
-                // Result not ready yet, we'll try again next tick
-                Err(TryRecvError::Empty) => (),
+                    Err(message) => failures.push(DownloadError::Decode { url, message }),
+                },
+                // Retries already happened in the download loop
+                Err(e) => failures.push(e),
             }
         }
 
-        for key in remove_set {
-            self.in_progress.remove(&key);
+        let now = Instant::now();
+        for e in failures {
+            println!("{}", e);
+            self.failed.insert(e, now);
+        }
+    }
+
+    // Called once per frame after drawing. Textures that weren't drawn this frame
+    // may be dropped to stay within the budget, and are requested again when next drawn.
+    pub fn end_frame(&mut self) {
+        self.cache.end_frame();
+        self.font_cache.end_frame();
+        self.downloads.update_priorities(Instant::now());
+    }
+}
+
+// Textures are created with 4 bytes per pixel
+fn texture_bytes(texture: &Texture) -> u64 {
+    let query = texture.query();
+    query.width as u64 * query.height as u64 * 4
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::AbortHandle;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_read_file_url() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/media/Space/nebula.png");
+        let url = Url::from_file_path(path.canonicalize().unwrap()).unwrap();
+        let resp = read_file(url.as_str()).await.unwrap();
+        assert!(resp.bytes.starts_with(b"\x89PNG"));
+        assert!(matches!(
+            read_file("file:///does-not-exist.png").await,
+            Err(DownloadError::Io { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_download_loop_drains_queue() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/media");
+        let (tx, rx) = mpsc::channel(16);
+        let mut receivers = Vec::new();
+        for (file, priority) in [
+            ("Space/nebula.png", Priority::Prefetch),
+            ("Nature/forest.png", Priority::Visible),
+            ("Space/earth_at_night.png", Priority::Selected),
+        ] {
+            let url = Url::from_file_path(dir.join(file).canonicalize().unwrap()).unwrap();
+            let (resp_tx, resp_rx) = oneshot::channel();
+            let req = DownloadRequest {
+                url: url.to_string(),
+                priority,
+                response_channel: resp_tx,
+                abort: None,
+            };
+            assert!(tx.send(DownloadMessage::Fetch(req)).await.is_ok());
+            receivers.push(resp_rx);
+        }
+        // Everything queued before the channel closed is still downloaded
+        drop(tx);
+        let retry = RetryPolicy::default();
+        download_loop(rx, Client::new(), None, retry, false, 1).await;
+        for resp_rx in receivers {
+            assert!(resp_rx.await.unwrap().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_abort_request() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/media/Space/nebula.png");
+        let url = Url::from_file_path(path.canonicalize().unwrap()).unwrap();
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let (abort, registration) = AbortHandle::new_pair();
+        let req = DownloadRequest {
+            url: url.to_string(),
+            priority: Priority::Visible,
+            response_channel: resp_tx,
+            abort: Some(registration),
+        };
+        // Slow requests take 2 seconds, so it's still running when it's aborted
+        let task = tokio::spawn(handle_request(
+            req,
+            Client::new(),
+            None,
+            RetryPolicy::default(),
+            true,
+        ));
+        abort.abort();
+        let started = Instant::now();
+        task.await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // Nothing is sent back
+        assert!(resp_rx.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serves_stale_images_right_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(DiskCache::open(dir.path().to_path_buf(), 1024).unwrap());
+        // Nothing listens on the discard port, so the revalidation can only fail
+        let url = "http://127.0.0.1:9/a.png";
+        let policy = CachePolicy {
+            etag: Some("\"v1\"".to_string()),
+            ..CachePolicy::default()
+        };
+        cache.put(url, &Bytes::from("stale"), policy).await;
+
+        let resp = cached_download(&Client::new(), &cache, url).await.unwrap();
+        assert_eq!(resp.bytes, "stale");
+        // The revalidation was started in the background
+        assert!(!cache.start_revalidation(url));
+    }
+
+    #[test]
+    fn test_permanent_errors() {
+        let status = |status| DownloadError::Status {
+            url: "https://example.com/a.jpg".to_string(),
+            status,
+        };
+        assert!(status(StatusCode::NOT_FOUND).is_permanent());
+        assert!(!status(StatusCode::TOO_MANY_REQUESTS).is_permanent());
+        assert!(!status(StatusCode::SERVICE_UNAVAILABLE).is_permanent());
+        let timeout = DownloadError::Timeout {
+            url: "https://example.com/a.jpg".to_string(),
+        };
+        assert!(!timeout.is_permanent());
+        assert_eq!(timeout.url(), "https://example.com/a.jpg");
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let retry = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            ..RetryPolicy::default()
+        };
+        for _ in 0..20 {
+            let first = retry.delay(0);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let second = retry.delay(1);
+            assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+            // Capped at max_delay
+            let late = retry.delay(10);
+            assert!(late >= Duration::from_millis(150) && late <= Duration::from_millis(300));
         }
     }
+
+    #[test]
+    fn test_visual_order() {
+        assert_eq!(visual_order("The Right Stuff"), "The Right Stuff");
+        // "shalom" in Hebrew, stored first letter first, displayed first letter rightmost
+        assert_eq!(
+            visual_order("\u{5e9}\u{5dc}\u{5d5}\u{5dd}"),
+            "\u{5dd}\u{5d5}\u{5dc}\u{5e9}"
+        );
+        assert_eq!(
+            visual_order("Disney+ \u{5e9}\u{5dc}\u{5d5}\u{5dd}"),
+            "Disney+ \u{5dd}\u{5d5}\u{5dc}\u{5e9}"
+        );
+    }
 }
@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt, TryStreamExt};
+
+use crate::json_parser::{ParseError, RowInfo, SetPage};
+
+// The layout only needs rows of tiles, so anything that can produce RowInfo can drive the UI.
+// JsonParser reads the bamgrid catalog, LibrarySource reads a much simpler schema
+// for media libraries that aren't served in the bamgrid format.
+
+/// Clamps a limit on how many things run at the same time, since a limit of 0 would never make progress
+pub fn concurrency_limit(concurrency: usize) -> usize {
+    concurrency.max(1)
+}
+
+/// Something the rows of the UI can be loaded from.
+/// Shared between the row loop's tasks, so page loading only borrows it.
+#[async_trait]
+pub trait ContentSource: Send + Sync {
+    /// Loads the rows of the home page.
+    /// Rows whose tiles live in a separate document are returned as placeholders
+    /// with no tiles and pending_ref set, to be filled in by load_ref_page.
+    async fn load_rows(&mut self) -> Result<Vec<RowInfo>, ParseError>;
+
+    /// Loads the first page of tiles for a placeholder row returned by load_rows
+    async fn load_ref_page(&self, ref_id: &str) -> Result<SetPage, ParseError>;
+
+    /// Loads the page of a set starting at item offset
+    async fn load_page(&self, set_id: &str, offset: usize) -> Result<SetPage, ParseError>;
+
+    /// Most documents loaded at the same time
+    fn concurrency(&self) -> usize;
+
+    /// Loads the rows and every referenced set, and every page of every set, up front.
+    /// Up to `concurrency` sets are loaded at the same time.
+    async fn load_all_rows(&mut self) -> Result<Vec<RowInfo>, ParseError> {
+        let rows = self.load_rows().await?;
+        let this = &*self;
+        // buffered keeps the rows in their original order
+        let rows: Vec<RowInfo> = stream::iter(rows)
+            .map(|mut row_info| async move {
+                if let Some(ref_id) = row_info.pending_ref.take() {
+                    row_info.append_page(this.load_ref_page(&ref_id).await?);
+                    if row_info.tiles.is_empty() {
+                        eprintln!("Could not parse tiles from set {}", ref_id);
+                    }
+                }
+                load_remaining_pages(this, &mut row_info).await?;
+                Ok(row_info)
+            })
+            .buffered(self.concurrency())
+            .try_collect()
+            .await?;
+        Ok(rows
+            .into_iter()
+            .filter(|row_info| !row_info.tiles.is_empty())
+            .collect())
+    }
+}
+
+// Loads every page after the ones already in the row, one after the other
+async fn load_remaining_pages<S: ContentSource + ?Sized>(
+    source: &S,
+    row_info: &mut RowInfo,
+) -> Result<(), ParseError> {
+    while row_info.has_more_pages() {
+        let set_id = row_info.set_id.clone().unwrap_or_default();
+        let page = source.load_page(&set_id, row_info.tiles.len()).await?;
+        if page.ends_set_early() {
+            eprintln!("Set {} ended before its last page", set_id);
+            row_info.total = row_info.tiles.len();
+            break;
+        }
+        row_info.append_page(page);
+    }
+    Ok(())
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use serde::Serialize;
+
+use crate::json_parser::{RowInfo, TileInfo};
+
+// Compares two parsed catalogs, e.g. yesterday's snapshot against the live home page.
+// Rows are matched by setId (falling back to the title) and tiles by their content ids,
+// so a retitled row or tile is reported as retitled rather than as removed and added.
+// A row or tile only counts as moved if its order relative to the others changed,
+// so inserting a row at the top doesn't report every row below it as moved.
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RowRef {
+    pub index: usize,
+    pub title: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Move {
+    pub title: String,
+    pub from: usize,
+    pub to: usize,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TileRef {
+    pub position: usize,
+    pub title: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Retitle {
+    /// Index of the row, or position of the tile, in the new catalog
+    pub index: usize,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ImageChange {
+    pub position: usize,
+    pub title: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// Changes to the tiles of a row that's in both catalogs
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct RowDiff {
+    /// Index of the row in the new catalog
+    pub index: usize,
+    pub title: String,
+    pub retitled: Option<Retitle>,
+    pub tiles_added: Vec<TileRef>,
+    pub tiles_removed: Vec<TileRef>,
+    pub tiles_moved: Vec<Move>,
+    pub tiles_retitled: Vec<Retitle>,
+    pub images_changed: Vec<ImageChange>,
+}
+
+impl RowDiff {
+    fn is_empty(&self) -> bool {
+        self.retitled.is_none()
+            && self.tiles_added.is_empty()
+            && self.tiles_removed.is_empty()
+            && self.tiles_moved.is_empty()
+            && self.tiles_retitled.is_empty()
+            && self.images_changed.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct CatalogDiff {
+    pub rows_added: Vec<RowRef>,
+    pub rows_removed: Vec<RowRef>,
+    pub rows_moved: Vec<Move>,
+    /// Only rows with at least one change
+    pub rows_changed: Vec<RowDiff>,
+}
+
+impl CatalogDiff {
+    pub fn is_empty(&self) -> bool {
+        self.rows_added.is_empty()
+            && self.rows_removed.is_empty()
+            && self.rows_moved.is_empty()
+            && self.rows_changed.is_empty()
+    }
+}
+
+impl fmt::Display for CatalogDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        for row in &self.rows_removed {
+            writeln!(f, "- row {} \"{}\"", row.index, row.title)?;
+        }
+        for row in &self.rows_added {
+            writeln!(f, "+ row {} \"{}\"", row.index, row.title)?;
+        }
+        for row in &self.rows_moved {
+            writeln!(
+                f,
+                "~ row \"{}\" moved {} -> {}",
+                row.title, row.from, row.to
+            )?;
+        }
+        for row in &self.rows_changed {
+            writeln!(f, "row {} \"{}\":", row.index, row.title)?;
+            if let Some(retitle) = &row.retitled {
+                writeln!(
+                    f,
+                    "    ~ retitled \"{}\" -> \"{}\"",
+                    retitle.old, retitle.new
+                )?;
+            }
+            for tile in &row.tiles_removed {
+                writeln!(f, "    - tile {} \"{}\"", tile.position, tile.title)?;
+            }
+            for tile in &row.tiles_added {
+                writeln!(f, "    + tile {} \"{}\"", tile.position, tile.title)?;
+            }
+            for tile in &row.tiles_moved {
+                writeln!(
+                    f,
+                    "    ~ tile \"{}\" moved {} -> {}",
+                    tile.title, tile.from, tile.to
+                )?;
+            }
+            for retitle in &row.tiles_retitled {
+                writeln!(
+                    f,
+                    "    ~ tile {} retitled \"{}\" -> \"{}\"",
+                    retitle.index, retitle.old, retitle.new
+                )?;
+            }
+            for change in &row.images_changed {
+                writeln!(
+                    f,
+                    "    ~ tile {} \"{}\" image {} -> {}",
+                    change.position, change.title, change.old, change.new
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn row_key(row: &RowInfo) -> String {
+    match &row.set_id {
+        Some(set_id) => format!("set {}", set_id),
+        None => format!("title {}", row.title),
+    }
+}
+
+fn tile_key(tile: &TileInfo) -> String {
+    let id = [
+        ("content", &tile.content_id),
+        ("family", &tile.family_id),
+        ("series", &tile.series_id),
+        ("collection", &tile.collection_id),
+    ]
+    .iter()
+    .find_map(|(kind, id)| id.as_ref().map(|id| format!("{} {}", kind, id)));
+    id.unwrap_or_else(|| format!("title {}", tile.title))
+}
+
+// The same set or title can appear twice, the nth copy is matched with the nth copy
+fn unique_keys<T>(items: &[T], key: impl Fn(&T) -> String) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    items
+        .iter()
+        .map(|item| {
+            let key = key(item);
+            let count = seen.entry(key.clone()).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                key
+            } else {
+                format!("{} #{}", key, count)
+            }
+        })
+        .collect()
+}
+
+// Keys that kept their order relative to each other, the longest common subsequence.
+// Anything common to both but outside of it has moved.
+fn in_order<'a>(old: &[&'a str], new: &[&'a str]) -> HashSet<&'a str> {
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut keys = HashSet::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            keys.insert(old[i]);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    keys
+}
+
+/// How items matched up between the old and new list
+struct Matching {
+    /// (old index, new index) of every item in both
+    common: Vec<(usize, usize)>,
+    added: Vec<usize>,
+    removed: Vec<usize>,
+    /// Subset of common that changed order
+    moved: Vec<(usize, usize)>,
+}
+
+fn match_keys(old: &[String], new: &[String]) -> Matching {
+    let old_index: HashMap<&str, usize> = old
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (key.as_str(), i))
+        .collect();
+    let new_index: HashMap<&str, usize> = new
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (key.as_str(), i))
+        .collect();
+
+    let common_old: Vec<&str> = old
+        .iter()
+        .map(String::as_str)
+        .filter(|key| new_index.contains_key(key))
+        .collect();
+    let common_new: Vec<&str> = new
+        .iter()
+        .map(String::as_str)
+        .filter(|key| old_index.contains_key(key))
+        .collect();
+    let stable = in_order(&common_old, &common_new);
+
+    let common: Vec<(usize, usize)> = common_new
+        .iter()
+        .map(|key| (old_index[key], new_index[key]))
+        .collect();
+    let moved = common_new
+        .iter()
+        .filter(|key| !stable.contains(*key))
+        .map(|key| (old_index[key], new_index[key]))
+        .collect();
+    Matching {
+        common,
+        added: (0..new.len())
+            .filter(|i| !old_index.contains_key(new[*i].as_str()))
+            .collect(),
+        removed: (0..old.len())
+            .filter(|i| !new_index.contains_key(old[*i].as_str()))
+            .collect(),
+        moved,
+    }
+}
+
+fn diff_row(old: &RowInfo, new: &RowInfo, index: usize) -> RowDiff {
+    let old_keys = unique_keys(&old.tiles, tile_key);
+    let new_keys = unique_keys(&new.tiles, tile_key);
+    let matching = match_keys(&old_keys, &new_keys);
+
+    let mut diff = RowDiff {
+        index,
+        title: new.title.clone(),
+        ..RowDiff::default()
+    };
+    if old.title != new.title {
+        diff.retitled = Some(Retitle {
+            index,
+            old: old.title.clone(),
+            new: new.title.clone(),
+        });
+    }
+    diff.tiles_added = matching
+        .added
+        .iter()
+        .map(|&i| TileRef {
+            position: i,
+            title: new.tiles[i].title.clone(),
+        })
+        .collect();
+    diff.tiles_removed = matching
+        .removed
+        .iter()
+        .map(|&i| TileRef {
+            position: i,
+            title: old.tiles[i].title.clone(),
+        })
+        .collect();
+    diff.tiles_moved = matching
+        .moved
+        .iter()
+        .map(|&(from, to)| Move {
+            title: new.tiles[to].title.clone(),
+            from,
+            to,
+        })
+        .collect();
+    for &(i, j) in &matching.common {
+        let (old_tile, new_tile) = (&old.tiles[i], &new.tiles[j]);
+        if old_tile.title != new_tile.title {
+            diff.tiles_retitled.push(Retitle {
+                index: j,
+                old: old_tile.title.clone(),
+                new: new_tile.title.clone(),
+            });
+        }
+        if old_tile.img_url != new_tile.img_url {
+            diff.images_changed.push(ImageChange {
+                position: j,
+                title: new_tile.title.clone(),
+                old: old_tile.img_url.clone(),
+                new: new_tile.img_url.clone(),
+            });
+        }
+    }
+    diff
+}
+
+/// Everything that changed going from old to new
+pub fn diff_catalogs(old: &[RowInfo], new: &[RowInfo]) -> CatalogDiff {
+    let old_keys = unique_keys(old, row_key);
+    let new_keys = unique_keys(new, row_key);
+    let matching = match_keys(&old_keys, &new_keys);
+
+    let row_ref = |rows: &[RowInfo], i: usize| RowRef {
+        index: i,
+        title: rows[i].title.clone(),
+    };
+    CatalogDiff {
+        rows_added: matching.added.iter().map(|&i| row_ref(new, i)).collect(),
+        rows_removed: matching.removed.iter().map(|&i| row_ref(old, i)).collect(),
+        rows_moved: matching
+            .moved
+            .iter()
+            .map(|&(from, to)| Move {
+                title: new[to].title.clone(),
+                from,
+                to,
+            })
+            .collect(),
+        rows_changed: matching
+            .common
+            .iter()
+            .map(|&(i, j)| diff_row(&old[i], &new[j], j))
+            .filter(|diff| !diff.is_empty())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::json_parser::RowStyle;
+
+    fn tile(content_id: &str, title: &str) -> TileInfo {
+        TileInfo {
+            title: title.to_string(),
+            img_url: format!("https://example.com/{}.jpg", content_id),
+            content_id: Some(content_id.to_string()),
+            ..TileInfo::default()
+        }
+    }
+
+    fn row(set_id: &str, title: &str, tiles: Vec<TileInfo>) -> RowInfo {
+        RowInfo {
+            title: title.to_string(),
+            style: RowStyle::Standard,
+            total: tiles.len(),
+            tiles,
+            pending_ref: None,
+            set_id: Some(set_id.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_no_changes() {
+        let rows = vec![row("a", "A", vec![tile("1", "One")])];
+        let rows_again = vec![row("a", "A", vec![tile("1", "One")])];
+        assert!(diff_catalogs(&rows, &rows_again).is_empty());
+    }
+
+    #[test]
+    fn test_rows() {
+        let old = vec![
+            row("a", "A", vec![]),
+            row("b", "B", vec![]),
+            row("c", "C", vec![]),
+        ];
+        let new = vec![
+            row("n", "New", vec![]),
+            row("c", "C", vec![]),
+            row("a", "A", vec![]),
+        ];
+        let diff = diff_catalogs(&old, &new);
+        assert_eq!(
+            diff.rows_added,
+            [RowRef {
+                index: 0,
+                title: "New".into()
+            }]
+        );
+        assert_eq!(
+            diff.rows_removed,
+            [RowRef {
+                index: 1,
+                title: "B".into()
+            }]
+        );
+        // Only one of A and C has to move to swap them
+        assert_eq!(diff.rows_moved.len(), 1);
+        assert!(diff.rows_changed.is_empty());
+    }
+
+    #[test]
+    fn test_insert_is_not_a_move() {
+        let old = vec![row("a", "A", vec![]), row("b", "B", vec![])];
+        let new = vec![
+            row("n", "New", vec![]),
+            row("a", "A", vec![]),
+            row("b", "B", vec![]),
+        ];
+        let diff = diff_catalogs(&old, &new);
+        assert_eq!(diff.rows_added.len(), 1);
+        assert!(diff.rows_moved.is_empty());
+    }
+
+    #[test]
+    fn test_tiles() {
+        let mut changed_image = tile("3", "Three");
+        changed_image.img_url = "https://example.com/new.jpg".to_string();
+        let old = vec![row(
+            "a",
+            "A",
+            vec![
+                tile("1", "One"),
+                tile("2", "Two"),
+                tile("3", "Three"),
+                tile("4", "Four"),
+            ],
+        )];
+        let new = vec![row(
+            "a",
+            "Renamed",
+            vec![tile("1", "Uno"), changed_image, tile("5", "Five")],
+        )];
+        let diff = diff_catalogs(&old, &new);
+        assert_eq!(diff.rows_changed.len(), 1);
+        let row = &diff.rows_changed[0];
+        assert_eq!(
+            row.retitled,
+            Some(Retitle {
+                index: 0,
+                old: "A".into(),
+                new: "Renamed".into()
+            })
+        );
+        assert_eq!(
+            row.tiles_added,
+            [TileRef {
+                position: 2,
+                title: "Five".into()
+            }]
+        );
+        assert_eq!(
+            row.tiles_removed,
+            [
+                TileRef {
+                    position: 1,
+                    title: "Two".into()
+                },
+                TileRef {
+                    position: 3,
+                    title: "Four".into()
+                }
+            ]
+        );
+        assert!(row.tiles_moved.is_empty());
+        assert_eq!(
+            row.tiles_retitled,
+            [Retitle {
+                index: 0,
+                old: "One".into(),
+                new: "Uno".into()
+            }]
+        );
+        assert_eq!(row.images_changed.len(), 1);
+        assert_eq!(row.images_changed[0].new, "https://example.com/new.jpg");
+    }
+
+    #[test]
+    fn test_tile_moved() {
+        let old = vec![row(
+            "a",
+            "A",
+            vec![tile("1", "One"), tile("2", "Two"), tile("3", "Three")],
+        )];
+        let new = vec![row(
+            "a",
+            "A",
+            vec![tile("3", "Three"), tile("1", "One"), tile("2", "Two")],
+        )];
+        let diff = diff_catalogs(&old, &new);
+        assert_eq!(
+            diff.rows_changed[0].tiles_moved,
+            [Move {
+                title: "Three".into(),
+                from: 2,
+                to: 0
+            }]
+        );
+    }
+}
@@ -0,0 +1,580 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, CACHE_CONTROL, ETAG, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Keeps downloaded images on disk between runs, so a repeat launch paints from disk
+// instead of downloading every tile again. Each image is stored in a file named by the hash
+// of its url, next to a small json file holding the url, the validators needed to revalidate
+// it and when it was last used. The index is kept in memory and rebuilt from the json files
+// when the cache is opened. Reads only update the index, their last_used is written back
+// when the cache is dropped, so a launch that only reads images doesn't rewrite every json file.
+// The cache is best effort: anything that can't be read or written is printed and skipped,
+// and the image is downloaded as if it wasn't cached.
+
+/// How a response may be cached, from its headers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CachePolicy {
+    /// Seconds the response is fresh for, from `Cache-Control: max-age`.
+    /// Responses without one are stale as soon as they're stored.
+    pub max_age: Option<u64>,
+    /// `Cache-Control: no-cache`, revalidate before every use even if max-age hasn't passed
+    pub no_cache: bool,
+    /// `Cache-Control: no-store`, never write the response to disk
+    pub no_store: bool,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CachePolicy {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let mut policy = CachePolicy {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            ..CachePolicy::default()
+        };
+        for value in headers.get_all(CACHE_CONTROL) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            for directive in value.split(',') {
+                let directive = directive.trim().to_ascii_lowercase();
+                match directive.split_once('=') {
+                    Some(("max-age", seconds)) => {
+                        policy.max_age = seconds.trim_matches('"').parse().ok()
+                    }
+                    _ if directive == "no-cache" => policy.no_cache = true,
+                    _ if directive == "no-store" => policy.no_store = true,
+                    _ => (),
+                }
+            }
+        }
+        policy
+    }
+
+    /// Keeps the validators we already had if a 304 response doesn't repeat them
+    fn merge(self, old: &CachePolicy) -> Self {
+        CachePolicy {
+            etag: self.etag.or_else(|| old.etag.clone()),
+            last_modified: self.last_modified.or_else(|| old.last_modified.clone()),
+            ..self
+        }
+    }
+}
+
+/// The json file stored next to each image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EntryMeta {
+    url: String,
+    policy: CachePolicy,
+    /// Unix seconds the response was received or last revalidated
+    stored_at: u64,
+    /// Unix seconds the image was last read, oldest is evicted first
+    last_used: u64,
+    size: u64,
+}
+
+impl EntryMeta {
+    fn is_fresh(&self, now: u64) -> bool {
+        match self.policy.max_age {
+            Some(max_age) => !self.policy.no_cache && now < self.stored_at + max_age,
+            None => false,
+        }
+    }
+}
+
+/// A cached image
+#[derive(Debug, Clone)]
+pub struct CachedImage {
+    pub bytes: Bytes,
+    /// Whether it can be used without revalidating it with the server
+    pub fresh: bool,
+    pub policy: CachePolicy,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, EntryMeta>,
+    total_bytes: u64,
+    // Stale entries already revalidated, or being revalidated, since the cache was opened
+    revalidated: HashSet<String>,
+    // Entries whose last_used changed since their json was written
+    dirty: HashSet<String>,
+}
+
+impl Index {
+    fn insert(&mut self, meta: EntryMeta) {
+        self.dirty.remove(&meta.url);
+        self.total_bytes += meta.size;
+        if let Some(old) = self.entries.insert(meta.url.clone(), meta) {
+            self.total_bytes -= old.size;
+        }
+    }
+
+    fn remove(&mut self, url: &str) -> Option<EntryMeta> {
+        self.revalidated.remove(url);
+        self.dirty.remove(url);
+        let meta = self.entries.remove(url)?;
+        self.total_bytes -= meta.size;
+        Some(meta)
+    }
+
+    // Removes least recently used entries until the cache fits in max_bytes
+    fn evict(&mut self, max_bytes: u64) -> Vec<EntryMeta> {
+        let mut evicted = Vec::new();
+        if self.total_bytes <= max_bytes {
+            return evicted;
+        }
+        let mut by_age: Vec<(u64, String)> = self
+            .entries
+            .values()
+            .map(|meta| (meta.last_used, meta.url.clone()))
+            .collect();
+        by_age.sort();
+        for (_, url) in by_age {
+            if self.total_bytes <= max_bytes {
+                break;
+            }
+            evicted.extend(self.remove(&url));
+        }
+        evicted
+    }
+}
+
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    // Only locked for map updates, never across an await
+    index: Mutex<Index>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+// Entries live in a subdirectory of the cache dir, so pointing --cache-dir at a directory
+// with other files in it never touches them
+const ENTRIES_DIR: &str = "dss-now-playing-images";
+
+fn file_stem(url: &str) -> String {
+    format!("{:x}", Sha256::digest(url.as_bytes()))
+}
+
+// Only files named like the ones the cache writes are ever deleted:
+// the sha256 of a url, optionally followed by .json, .tmp or .json.tmp
+fn is_cache_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    let (stem, suffix) = name.split_at(name.find('.').unwrap_or(name.len()));
+    stem.len() == 64
+        && stem.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && matches!(suffix, "" | ".json" | ".tmp" | ".json.tmp")
+}
+
+impl DiskCache {
+    /// `dss-now-playing` under the platform's cache directory,
+    /// e.g. `$XDG_CACHE_HOME` or `~/.cache` on Linux
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("dss-now-playing"))
+    }
+
+    /// Opens the cache in a subdirectory of dir, creating it if needed,
+    /// and evicts entries if it's over max_bytes
+    pub fn open(dir: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.join(ENTRIES_DIR);
+        fs::create_dir_all(&dir)?;
+        let mut index = Index::default();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !is_cache_file(&path) {
+                continue;
+            }
+            // Left behind by a write that never finished
+            if path.extension() == Some("tmp".as_ref()) {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if path.extension() != Some("json".as_ref()) {
+                // An image whose json was never written can't be looked up
+                if !path.with_extension("json").exists() {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            }
+            let meta = fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<EntryMeta>(&json).ok());
+            let body = path.with_extension("");
+            match meta {
+                // The body is written first, so a missing or short body means an interrupted write
+                Some(meta) if fs::metadata(&body).is_ok_and(|m| m.len() == meta.size) => {
+                    index.insert(meta)
+                }
+                _ => {
+                    let _ = fs::remove_file(&path);
+                    let _ = fs::remove_file(&body);
+                }
+            }
+        }
+        let cache = Self {
+            dir,
+            max_bytes,
+            index: Mutex::new(index),
+        };
+        let evicted = cache.index.lock().unwrap().evict(max_bytes);
+        for meta in evicted {
+            let _ = fs::remove_file(cache.body_path(&meta.url));
+            let _ = fs::remove_file(cache.meta_path(&meta.url));
+        }
+        Ok(cache)
+    }
+
+    fn body_path(&self, url: &str) -> PathBuf {
+        self.dir.join(file_stem(url))
+    }
+
+    fn meta_path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", file_stem(url)))
+    }
+
+    /// Total size of the cached images
+    pub fn size(&self) -> u64 {
+        self.index.lock().unwrap().total_bytes
+    }
+
+    /// Reads a cached image, marking it as used
+    pub async fn get(&self, url: &str) -> Option<CachedImage> {
+        let now = now();
+        let meta = {
+            let mut index = self.index.lock().unwrap();
+            let meta = index.entries.get_mut(url)?;
+            meta.last_used = now;
+            let meta = meta.clone();
+            index.dirty.insert(url.to_string());
+            meta
+        };
+        match tokio::fs::read(self.body_path(url)).await {
+            Ok(bytes) => Some(CachedImage {
+                bytes: Bytes::from(bytes),
+                fresh: meta.is_fresh(now),
+                policy: meta.policy,
+            }),
+            Err(e) => {
+                println!("Dropping cached {}: {}", url, e);
+                self.remove(url).await;
+                None
+            }
+        }
+    }
+
+    /// Claims the revalidation of a stale image for the caller.
+    /// False if it has already been claimed since the cache was opened, so each image is only
+    /// checked with the server once per run, however often it's read.
+    pub fn start_revalidation(&self, url: &str) -> bool {
+        self.index
+            .lock()
+            .unwrap()
+            .revalidated
+            .insert(url.to_string())
+    }
+
+    /// Stores a downloaded image, evicting the least recently used images if the cache is full
+    pub async fn put(&self, url: &str, bytes: &Bytes, policy: CachePolicy) {
+        let size = bytes.len() as u64;
+        if policy.no_store || size > self.max_bytes {
+            self.remove(url).await;
+            return;
+        }
+        if let Err(e) = write_atomic(&self.body_path(url), bytes).await {
+            println!("Could not cache {}: {}", url, e);
+            return;
+        }
+        let now = now();
+        let meta = EntryMeta {
+            url: url.to_string(),
+            policy,
+            stored_at: now,
+            last_used: now,
+            size,
+        };
+        self.write_meta(&meta).await;
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(meta);
+            index.evict(self.max_bytes)
+        };
+        for meta in evicted {
+            self.delete_files(&meta.url).await;
+        }
+    }
+
+    /// Marks a cached image as fresh again after the server answered 304 Not Modified
+    pub async fn refresh(&self, url: &str, policy: CachePolicy) {
+        let meta = {
+            let mut index = self.index.lock().unwrap();
+            let meta = match index.entries.get_mut(url) {
+                Some(meta) => meta,
+                None => return,
+            };
+            meta.policy = policy.merge(&meta.policy);
+            meta.stored_at = now();
+            let meta = meta.clone();
+            index.dirty.remove(url);
+            meta
+        };
+        self.write_meta(&meta).await;
+    }
+
+    pub async fn remove(&self, url: &str) {
+        let removed = self.index.lock().unwrap().remove(url);
+        if removed.is_some() {
+            self.delete_files(url).await;
+        }
+    }
+
+    /// Writes the last_used of images read since the last flush. Called when the cache is dropped.
+    pub fn flush(&self) {
+        let dirty: Vec<EntryMeta> = {
+            let mut index = self.index.lock().unwrap();
+            let dirty = std::mem::take(&mut index.dirty);
+            dirty
+                .iter()
+                .filter_map(|url| index.entries.get(url).cloned())
+                .collect()
+        };
+        for meta in dirty {
+            let json = serde_json::to_vec(&meta).unwrap();
+            let path = self.meta_path(&meta.url);
+            let tmp = tmp_path(&path);
+            if let Err(e) = fs::write(&tmp, &json).and_then(|_| fs::rename(&tmp, &path)) {
+                println!("Could not cache {}: {}", meta.url, e);
+            }
+        }
+    }
+
+    async fn write_meta(&self, meta: &EntryMeta) {
+        let json = serde_json::to_vec(meta).unwrap();
+        if let Err(e) = write_atomic(&self.meta_path(&meta.url), &json).await {
+            println!("Could not cache {}: {}", meta.url, e);
+        }
+    }
+
+    async fn delete_files(&self, url: &str) {
+        let _ = tokio::fs::remove_file(self.meta_path(url)).await;
+        let _ = tokio::fs::remove_file(self.body_path(url)).await;
+    }
+}
+
+impl Drop for DiskCache {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+// Writes next to the file and renames it into place, so a download cancelled halfway
+// never leaves a truncated image behind an entry that's already in the index
+async fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path(path);
+    tokio::fs::write(&tmp, bytes).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_cache_policy() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("public, Max-Age=3600"),
+        );
+        headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+        let policy = CachePolicy::from_headers(&headers);
+        assert_eq!(policy.max_age, Some(3600));
+        assert_eq!(policy.etag.as_deref(), Some("\"abc\""));
+        assert!(!policy.no_cache && !policy.no_store);
+
+        headers.insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("no-cache, no-store"),
+        );
+        let policy = CachePolicy::from_headers(&headers);
+        assert_eq!(policy.max_age, None);
+        assert!(policy.no_cache && policy.no_store);
+    }
+
+    #[tokio::test]
+    async fn test_put_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path().to_path_buf(), 1024).unwrap();
+        let fresh = CachePolicy {
+            max_age: Some(3600),
+            ..CachePolicy::default()
+        };
+        let stale = CachePolicy {
+            etag: Some("\"v1\"".to_string()),
+            ..CachePolicy::default()
+        };
+        cache.put("https://a", &Bytes::from("aaaa"), fresh).await;
+        cache.put("https://b", &Bytes::from("bb"), stale).await;
+        cache
+            .put(
+                "https://c",
+                &Bytes::from("c"),
+                CachePolicy {
+                    no_store: true,
+                    ..CachePolicy::default()
+                },
+            )
+            .await;
+        assert!(cache.get("https://c").await.is_none());
+        assert_eq!(cache.size(), 6);
+
+        // A new launch reads the index back from disk
+        let cache = DiskCache::open(dir.path().to_path_buf(), 1024).unwrap();
+        let a = cache.get("https://a").await.unwrap();
+        assert_eq!(a.bytes, "aaaa");
+        assert!(a.fresh);
+        let b = cache.get("https://b").await.unwrap();
+        assert!(!b.fresh);
+        assert!(cache.start_revalidation("https://b"));
+        assert!(!cache.start_revalidation("https://b"));
+
+        // A 304 without validators keeps the old ones
+        cache
+            .refresh(
+                "https://b",
+                CachePolicy {
+                    max_age: Some(60),
+                    ..CachePolicy::default()
+                },
+            )
+            .await;
+        let b = cache.get("https://b").await.unwrap();
+        assert!(b.fresh);
+        assert_eq!(b.policy.etag.as_deref(), Some("\"v1\""));
+    }
+
+    #[tokio::test]
+    async fn test_last_used_written_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path().to_path_buf(), 1024).unwrap();
+        cache
+            .put("https://a", &Bytes::from("aaaa"), CachePolicy::default())
+            .await;
+        let meta = {
+            let mut index = cache.index.lock().unwrap();
+            let meta = index.entries.get_mut("https://a").unwrap();
+            meta.last_used = 0;
+            meta.clone()
+        };
+        cache.write_meta(&meta).await;
+        let meta_path = cache.meta_path("https://a");
+        let last_used_on_disk = || {
+            let json = fs::read(&meta_path).unwrap();
+            serde_json::from_slice::<EntryMeta>(&json)
+                .unwrap()
+                .last_used
+        };
+
+        // Reading doesn't touch the disk
+        assert!(cache.get("https://a").await.is_some());
+        assert_eq!(last_used_on_disk(), 0);
+        drop(cache);
+        assert!(last_used_on_disk() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_foreign_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = dir.path().join(ENTRIES_DIR);
+        fs::create_dir_all(&entries).unwrap();
+        let orphan = entries.join(file_stem("https://a"));
+        let foreign = [
+            dir.path().join("holiday.jpg"),
+            dir.path().join("notes.json"),
+            dir.path().join("draft.tmp"),
+            entries.join("readme.txt"),
+            entries.join("settings.json"),
+        ];
+        for path in foreign.iter().chain(std::iter::once(&orphan)) {
+            fs::write(path, "not a cache entry").unwrap();
+        }
+
+        DiskCache::open(dir.path().to_path_buf(), 1024).unwrap();
+        for path in &foreign {
+            assert!(path.exists(), "{} was deleted", path.display());
+        }
+        // A cache file whose json was never written is cleaned up
+        assert!(!orphan.exists());
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path().to_path_buf(), 10).unwrap();
+        let policy = CachePolicy::default();
+        cache
+            .put("https://a", &Bytes::from("aaaa"), policy.clone())
+            .await;
+        cache
+            .put("https://b", &Bytes::from("bbbb"), policy.clone())
+            .await;
+        // Usage is tracked in seconds, so mark b as used long ago
+        cache
+            .index
+            .lock()
+            .unwrap()
+            .entries
+            .get_mut("https://b")
+            .unwrap()
+            .last_used = 0;
+        cache
+            .put("https://c", &Bytes::from("cccc"), policy.clone())
+            .await;
+
+        assert!(cache.get("https://b").await.is_none());
+        assert!(cache.get("https://a").await.is_some());
+        assert!(cache.get("https://c").await.is_some());
+        assert_eq!(cache.size(), 8);
+        // The evicted files are gone too
+        assert_eq!(
+            fs::read_dir(dir.path().join(ENTRIES_DIR)).unwrap().count(),
+            4
+        );
+
+        // Too big to ever fit
+        cache
+            .put("https://d", &Bytes::from("d".repeat(11)), policy)
+            .await;
+        assert!(cache.get("https://d").await.is_none());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::async_resource_manager::DownloadRequest;
+
+// Downloads wait here until the download loop has room to start them.
+// Whatever the user is looking at goes first: the selected tile, then the rest of the screen,
+// then images fetched ahead of time. The resource manager moves requests between levels as
+// tiles come and go, and cancels a request by dropping its receiver, which is noticed
+// before the request starts, and aborting it, which stops it once it has.
+
+/// How urgently an image is needed, most urgent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// The selected tile
+    Selected,
+    /// Any other tile on screen
+    Visible,
+    /// Close to the screen, fetched ahead of time
+    Prefetch,
+    /// Scrolled away while it was waiting
+    Offscreen,
+}
+
+/// Queued requests ordered by priority, then by when they were queued
+#[derive(Default)]
+pub struct DownloadQueue {
+    // Keyed by priority and a sequence number, so equal priorities come out in order
+    queue: BTreeMap<(Priority, u64), DownloadRequest>,
+    // Where each url sits in the queue
+    keys: HashMap<String, (Priority, u64)>,
+    next_seq: u64,
+}
+
+impl DownloadQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a request, replacing any queued request for the same url
+    pub fn push(&mut self, req: DownloadRequest) {
+        let key = (req.priority, self.next_seq);
+        self.next_seq += 1;
+        if let Some(old) = self.keys.insert(req.url.clone(), key) {
+            self.queue.remove(&old);
+        }
+        self.queue.insert(key, req);
+    }
+
+    /// Moves a queued request to another priority, at the back of it.
+    /// Does nothing if the request isn't queued, e.g. because it has already started.
+    pub fn set_priority(&mut self, url: &str, priority: Priority) {
+        let old = match self.keys.get(url) {
+            Some(old) if old.0 != priority => *old,
+            _ => return,
+        };
+        if let Some(mut req) = self.queue.remove(&old) {
+            req.priority = priority;
+            self.keys.remove(url);
+            self.push(req);
+        }
+    }
+
+    /// Takes the most urgent request that hasn't been cancelled
+    pub fn pop(&mut self) -> Option<DownloadRequest> {
+        loop {
+            let key = *self.queue.keys().next()?;
+            let req = self.queue.remove(&key)?;
+            self.keys.remove(&req.url);
+            // Nobody is waiting for the image any more
+            if !req.response_channel.is_closed() {
+                return Some(req);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::async_resource_manager::DownloadResult;
+    use tokio::sync::oneshot;
+
+    fn request(
+        url: &str,
+        priority: Priority,
+    ) -> (DownloadRequest, oneshot::Receiver<DownloadResult>) {
+        let (tx, rx) = oneshot::channel();
+        let req = DownloadRequest {
+            url: url.to_string(),
+            priority,
+            response_channel: tx,
+            abort: None,
+        };
+        (req, rx)
+    }
+
+    fn pop_url(queue: &mut DownloadQueue) -> Option<String> {
+        queue.pop().map(|req| req.url)
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let mut queue = DownloadQueue::new();
+        let mut receivers = Vec::new();
+        for (url, priority) in [
+            ("prefetch", Priority::Prefetch),
+            ("visible-1", Priority::Visible),
+            ("selected", Priority::Selected),
+            ("visible-2", Priority::Visible),
+        ] {
+            let (req, rx) = request(url, priority);
+            queue.push(req);
+            receivers.push(rx);
+        }
+        assert_eq!(queue.len(), 4);
+        assert_eq!(pop_url(&mut queue).as_deref(), Some("selected"));
+        assert_eq!(pop_url(&mut queue).as_deref(), Some("visible-1"));
+        assert_eq!(pop_url(&mut queue).as_deref(), Some("visible-2"));
+        assert_eq!(pop_url(&mut queue).as_deref(), Some("prefetch"));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_set_priority() {
+        let mut queue = DownloadQueue::new();
+        let (a, _a_rx) = request("a", Priority::Visible);
+        let (b, _b_rx) = request("b", Priority::Visible);
+        queue.push(a);
+        queue.push(b);
+        // a scrolled away, b was selected
+        queue.set_priority("a", Priority::Offscreen);
+        queue.set_priority("b", Priority::Selected);
+        // Not queued
+        queue.set_priority("c", Priority::Selected);
+        assert_eq!(queue.len(), 2);
+        let b = queue.pop().unwrap();
+        assert_eq!((b.url.as_str(), b.priority), ("b", Priority::Selected));
+        assert_eq!(pop_url(&mut queue).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_skips_cancelled() {
+        let mut queue = DownloadQueue::new();
+        let (a, a_rx) = request("a", Priority::Visible);
+        let (b, _b_rx) = request("b", Priority::Visible);
+        queue.push(a);
+        queue.push(b);
+        drop(a_rx);
+        assert_eq!(pop_url(&mut queue).as_deref(), Some("b"));
+        assert!(queue.pop().is_none());
+    }
+}
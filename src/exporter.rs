@@ -0,0 +1,228 @@
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+use crate::json_parser::{RowInfo, TileInfo};
+
+// Flattens parsed rows into one record per tile, for tools that want the catalog
+// as data rather than on screen. Rows come from load_all_rows, so the export holds
+// exactly the rows and tiles the UI would show, in the same order.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// One json object per line
+    JsonLines,
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(ExportFormat::JsonLines),
+            "csv" => Ok(ExportFormat::Csv),
+            other => Err(format!(
+                "unknown export format {}, expected jsonl or csv",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportImage<'a> {
+    pub aspect_ratio: f32,
+    pub url: &'a str,
+    pub master_width: Option<u32>,
+}
+
+/// A single tile, along with the row it's in
+#[derive(Debug, Serialize)]
+pub struct ExportRecord<'a> {
+    /// Index of the row on screen
+    pub row: usize,
+    pub row_title: &'a str,
+    pub row_style: &'a str,
+    /// Index of the tile within its row
+    pub position: usize,
+    pub title: &'a str,
+    pub content_type: String,
+    pub content_id: Option<&'a str>,
+    pub family_id: Option<&'a str>,
+    pub series_id: Option<&'a str>,
+    pub collection_id: Option<&'a str>,
+    pub rating: Option<&'a str>,
+    pub release_year: Option<u32>,
+    /// The 1.78 image, empty if the tile has none
+    pub image_url: &'a str,
+    pub images: Vec<ExportImage<'a>>,
+    pub brief_description: Option<&'a str>,
+    pub full_description: Option<&'a str>,
+}
+
+const CSV_HEADER: [&str; 16] = [
+    "row",
+    "row_title",
+    "row_style",
+    "position",
+    "title",
+    "content_type",
+    "content_id",
+    "family_id",
+    "series_id",
+    "collection_id",
+    "rating",
+    "release_year",
+    "image_url",
+    "images",
+    "brief_description",
+    "full_description",
+];
+
+impl<'a> ExportRecord<'a> {
+    fn new(row: usize, row_info: &'a RowInfo, position: usize, tile: &'a TileInfo) -> Self {
+        Self {
+            row,
+            row_title: &row_info.title,
+            row_style: row_info.style.name(),
+            position,
+            title: &tile.title,
+            content_type: tile.content_type.to_string(),
+            content_id: tile.content_id.as_deref(),
+            family_id: tile.family_id.as_deref(),
+            series_id: tile.series_id.as_deref(),
+            collection_id: tile.collection_id.as_deref(),
+            rating: tile.rating.as_deref(),
+            release_year: tile.release_year,
+            image_url: &tile.img_url,
+            images: tile
+                .images
+                .iter()
+                .map(|image| ExportImage {
+                    aspect_ratio: image.aspect_ratio,
+                    url: &image.url,
+                    master_width: image.master_width,
+                })
+                .collect(),
+            brief_description: tile.brief_description.as_deref(),
+            full_description: tile.full_description.as_deref(),
+        }
+    }
+
+    // Csv has no lists, so images become `ratio=url` pairs separated by spaces
+    fn csv_fields(&self) -> [String; 16] {
+        let opt = |value: Option<&str>| value.unwrap_or_default().to_string();
+        let images: Vec<String> = self
+            .images
+            .iter()
+            .map(|image| format!("{}={}", image.aspect_ratio, image.url))
+            .collect();
+        [
+            self.row.to_string(),
+            self.row_title.to_string(),
+            self.row_style.to_string(),
+            self.position.to_string(),
+            self.title.to_string(),
+            self.content_type.clone(),
+            opt(self.content_id),
+            opt(self.family_id),
+            opt(self.series_id),
+            opt(self.collection_id),
+            opt(self.rating),
+            self.release_year
+                .map(|year| year.to_string())
+                .unwrap_or_default(),
+            self.image_url.to_string(),
+            images.join(" "),
+            opt(self.brief_description),
+            opt(self.full_description),
+        ]
+    }
+}
+
+/// Every tile of every row, in the order they're shown
+pub fn export_records(rows: &[RowInfo]) -> Vec<ExportRecord<'_>> {
+    rows.iter()
+        .enumerate()
+        .flat_map(|(row, row_info)| {
+            row_info
+                .tiles
+                .iter()
+                .enumerate()
+                .map(move |(position, tile)| ExportRecord::new(row, row_info, position, tile))
+        })
+        .collect()
+}
+
+pub fn write_export(rows: &[RowInfo], format: ExportFormat, out: impl io::Write) -> io::Result<()> {
+    let records = export_records(rows);
+    match format {
+        ExportFormat::JsonLines => {
+            let mut out = io::BufWriter::new(out);
+            for record in &records {
+                serde_json::to_writer(&mut out, record)?;
+                writeln!(out)?;
+            }
+            out.flush()
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(CSV_HEADER)?;
+            for record in &records {
+                writer.write_record(record.csv_fields())?;
+            }
+            writer.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::json_parser::{DocumentSource, JsonParser};
+    use std::path::PathBuf;
+
+    async fn fixture_rows() -> Vec<RowInfo> {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/snapshot");
+        let mut parser = JsonParser::with_source(DocumentSource::Directory(dir));
+        parser.parse_all_rows().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_json_lines() {
+        let rows = fixture_rows().await;
+        let mut out = Vec::new();
+        write_export(&rows, ExportFormat::JsonLines, &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // Every tile the UI shows, including later pages
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0]["row_title"], "New to Disney+");
+        assert_eq!(lines[0]["title"], "The Right Stuff");
+        assert_eq!(lines[0]["content_type"], "series");
+        assert_eq!(lines[0]["images"].as_array().unwrap().len(), 2);
+        assert_eq!(lines[4]["row"], 1);
+        assert_eq!(lines[4]["position"], 2);
+        assert_eq!(lines[4]["title"], "Andor");
+    }
+
+    #[tokio::test]
+    async fn test_export_csv() {
+        let rows = fixture_rows().await;
+        let mut out = Vec::new();
+        write_export(&rows, ExportFormat::Csv, &mut out).unwrap();
+        let mut reader = csv::Reader::from_reader(out.as_slice());
+        assert_eq!(reader.headers().unwrap(), CSV_HEADER.as_ref());
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 5);
+        assert_eq!(&records[0][4], "The Right Stuff");
+        assert!(records[0][13].starts_with("0.71=https://"));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::async_resource_manager::DownloadError;
+
+// Remembers images whose download or decode failed, so they aren't requested again every frame.
+// Permanent failures are kept for good. Anything else is forgotten once retry_after has passed,
+// and the image is requested again the next time it's drawn. The download loop has already
+// retried it by then, so retry_after is meant to be a lot longer than its backoff.
+
+/// Why an image isn't shown
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureState {
+    /// Failed for a reason that may go away, and will be requested again after a while
+    Retrying(DownloadError),
+    /// Will never load
+    Failed(DownloadError),
+}
+
+struct Failure {
+    error: DownloadError,
+    // None if the failure is permanent
+    retry_at: Option<Instant>,
+}
+
+pub struct FailedImages {
+    failures: HashMap<String, Failure>,
+    retry_after: Duration,
+}
+
+impl FailedImages {
+    pub fn new(retry_after: Duration) -> Self {
+        Self {
+            failures: HashMap::new(),
+            retry_after,
+        }
+    }
+
+    /// Records a failure of the image at the error's url, replacing any earlier one
+    pub fn insert(&mut self, error: DownloadError, now: Instant) {
+        let retry_at = if error.is_permanent() {
+            None
+        } else {
+            Some(now + self.retry_after)
+        };
+        self.failures
+            .insert(error.url().to_string(), Failure { error, retry_at });
+    }
+
+    /// Why the image at url isn't shown, or None if it should be requested.
+    /// A failure whose retry time has come is forgotten.
+    pub fn state(&mut self, url: &str, now: Instant) -> Option<FailureState> {
+        let failure = self.failures.get(url)?;
+        match failure.retry_at {
+            None => Some(FailureState::Failed(failure.error.clone())),
+            Some(retry_at) if now < retry_at => Some(FailureState::Retrying(failure.error.clone())),
+            Some(_) => {
+                self.failures.remove(url);
+                None
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::StatusCode;
+
+    const URL: &str = "https://example.com/a.jpg";
+
+    fn status(status: StatusCode) -> DownloadError {
+        DownloadError::Status {
+            url: URL.to_string(),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_permanent_failure() {
+        let mut failed = FailedImages::new(Duration::from_secs(30));
+        let now = Instant::now();
+        assert_eq!(failed.state(URL, now), None);
+        failed.insert(status(StatusCode::NOT_FOUND), now);
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(
+            failed.state(URL, later),
+            Some(FailureState::Failed(status(StatusCode::NOT_FOUND)))
+        );
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut failed = FailedImages::new(Duration::from_secs(30));
+        let now = Instant::now();
+        failed.insert(status(StatusCode::SERVICE_UNAVAILABLE), now);
+        assert_eq!(
+            failed.state(URL, now + Duration::from_secs(29)),
+            Some(FailureState::Retrying(status(
+                StatusCode::SERVICE_UNAVAILABLE
+            )))
+        );
+        // Forgotten once it's time to try again
+        assert_eq!(failed.state(URL, now + Duration::from_secs(30)), None);
+        assert!(failed.is_empty());
+
+        // A later failure replaces the earlier one
+        failed.insert(DownloadError::Timeout { url: URL.into() }, now);
+        failed.insert(status(StatusCode::GONE), now);
+        assert_eq!(failed.len(), 1);
+        assert!(matches!(
+            failed.state(URL, now + Duration::from_secs(60)),
+            Some(FailureState::Failed(_))
+        ));
+    }
+}
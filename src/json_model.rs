@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+// Strongly-typed model of the home and set json documents.
+// Only the fields the app actually uses are modeled. Serde ignores everything else,
+// so new fields on the server side won't break us, but a missing or mistyped field
+// that we depend on will fail deserialization with the path to the offending field.
+
+/// Top level of home.json.
+/// With `serde_json::Value` as C, each container is left as raw json to be decoded on its own.
+#[derive(Debug, Deserialize)]
+pub struct HomeDocument<C = Container> {
+    pub data: HomeData<C>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HomeData<C = Container> {
+    #[serde(rename = "StandardCollection")]
+    pub standard_collection: StandardCollection<C>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StandardCollection<C = Container> {
+    pub containers: Vec<C>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Container {
+    pub set: ContainerSet,
+    /// How the row should be displayed, e.g. "standard" or "hero"
+    pub style: Option<String>,
+}
+
+/// The set inside a container on the home page.
+/// Either the items are inlined directly, or the container references a set
+/// that has to be fetched separately.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum ContainerSet {
+    CuratedSet(CuratedSet),
+    TrendingSet(TrendingSet),
+    PersonalizedCuratedSet(PersonalizedCuratedSet),
+    SetRef(SetRef),
+    #[serde(other)]
+    Unknown,
+}
+
+impl ContainerSet {
+    /// The set's "type", as it appears in the json
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ContainerSet::CuratedSet(_) => "CuratedSet",
+            ContainerSet::TrendingSet(_) => "TrendingSet",
+            ContainerSet::PersonalizedCuratedSet(_) => "PersonalizedCuratedSet",
+            ContainerSet::SetRef(_) => "SetRef",
+            ContainerSet::Unknown => "unknown",
+        }
+    }
+}
+
+/// A set of items with a title. All the inlined set types share this shape.
+/// Large sets are split into pages, each holding up to meta.page_size items.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CuratedSet {
+    pub set_id: Option<String>,
+    pub text: SetText,
+    pub items: Vec<Item>,
+    pub meta: Option<SetMeta>,
+}
+
+/// Paging info of a set, e.g. `{"hits": 30, "offset": 0, "page_size": 15}`
+#[derive(Debug, Deserialize)]
+pub struct SetMeta {
+    /// Total number of items in the set, across every page
+    pub hits: usize,
+    /// Index of the first item of this page within the set
+    pub offset: usize,
+    pub page_size: usize,
+}
+
+pub type TrendingSet = CuratedSet;
+pub type PersonalizedCuratedSet = CuratedSet;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetRef {
+    pub ref_id: String,
+    pub text: SetText,
+}
+
+/// Top level of sets/{refId}.json, and of every further page of a set
+#[derive(Debug, Deserialize)]
+pub struct SetDocument {
+    pub data: RefSet,
+}
+
+/// The set returned when resolving a SetRef.
+/// Keyed by set type, e.g. `{"CuratedSet": {...}}`
+#[derive(Debug, Deserialize)]
+pub enum RefSet {
+    CuratedSet(CuratedSet),
+    TrendingSet(TrendingSet),
+    PersonalizedCuratedSet(PersonalizedCuratedSet),
+}
+
+impl RefSet {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RefSet::CuratedSet(_) => "CuratedSet",
+            RefSet::TrendingSet(_) => "TrendingSet",
+            RefSet::PersonalizedCuratedSet(_) => "PersonalizedCuratedSet",
+        }
+    }
+
+    /// Id further pages of the set are fetched by.
+    /// Referenced sets are looked up by setId, so without one the refId they were fetched by doubles as one.
+    pub fn page_id<'a>(&'a self, ref_id: &'a str) -> &'a str {
+        self.set().set_id.as_deref().unwrap_or(ref_id)
+    }
+
+    fn set(&self) -> &CuratedSet {
+        match self {
+            RefSet::CuratedSet(set)
+            | RefSet::TrendingSet(set)
+            | RefSet::PersonalizedCuratedSet(set) => set,
+        }
+    }
+
+    pub fn into_set(self) -> CuratedSet {
+        match self {
+            RefSet::CuratedSet(set)
+            | RefSet::TrendingSet(set)
+            | RefSet::PersonalizedCuratedSet(set) => set,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetText {
+    pub title: SetTitle,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetTitle {
+    pub full: SetFullTitle,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetFullTitle {
+    pub set: LocalizedText,
+}
+
+/// A single item (tile) within a set
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Item {
+    /// e.g. "DmcSeries", "DmcVideo" or "StandardCollection"
+    #[serde(rename = "type")]
+    pub item_type: Option<String>,
+    pub content_id: Option<String>,
+    pub family_id: Option<String>,
+    pub encoded_series_id: Option<String>,
+    pub collection_id: Option<String>,
+    pub text: TextBlock,
+    pub image: ImageBlock,
+    #[serde(default)]
+    pub ratings: Vec<Rating>,
+    #[serde(default)]
+    pub releases: Vec<Release>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rating {
+    /// e.g. "TVPG" or "MPAA"
+    pub system: Option<String>,
+    /// e.g. "TV-PG"
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Release {
+    pub release_year: Option<u32>,
+}
+
+/// Text attached to an item.
+#[derive(Debug, Deserialize)]
+pub struct TextBlock {
+    pub title: TextField,
+    pub description: Option<DescriptionField>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TextField {
+    pub full: TextVariants,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DescriptionField {
+    pub brief: Option<TextVariants>,
+    pub full: Option<TextVariants>,
+}
+
+/// Text keyed by the entity it came from ("series", "program", "collection", ...).
+pub type TextVariants = HashMap<String, LocalizedText>;
+
+/// Text keyed by language tag, e.g. "en" or "fr-CA". Every text has a "default" entry.
+pub type LocalizedText = HashMap<String, TextContent>;
+
+#[derive(Debug, Deserialize)]
+pub struct TextContent {
+    pub content: String,
+}
+
+/// Images attached to an item, keyed first by aspect ratio (e.g. "1.78"),
+/// then by source entity ("series", "program", ...), then by "default".
+#[derive(Debug, Deserialize)]
+pub struct ImageBlock {
+    pub tile: HashMap<String, ImageVariants>,
+}
+
+pub type ImageVariants = HashMap<String, HashMap<String, ImageContent>>;
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageContent {
+    pub url: String,
+    pub master_width: Option<u32>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn container(json: &str) -> Container {
+        serde_json::from_str(json).unwrap()
+    }
+
+    const TEXT: &str = r#"{"title": {"full": {"set": {"default": {"content": "Trending"}}}}}"#;
+
+    #[test]
+    fn test_container_set_types() {
+        let set_ref = container(&format!(
+            r#"{{"set": {{"type": "SetRef", "refId": "abc", "text": {}}}, "style": "hero"}}"#,
+            TEXT
+        ));
+        assert_eq!(set_ref.style.as_deref(), Some("hero"));
+        match set_ref.set {
+            ContainerSet::SetRef(set_ref) => {
+                assert_eq!(set_ref.ref_id, "abc");
+                assert_eq!(set_ref.text.title.full.set["default"].content, "Trending");
+            }
+            other => panic!("Expected a SetRef, got {:?}", other),
+        }
+
+        let trending = container(&format!(
+            r#"{{"set": {{"type": "TrendingSet", "text": {}, "items": [],
+                "meta": {{"hits": 30, "offset": 0, "page_size": 15}}}}}}"#,
+            TEXT
+        ));
+        assert_eq!(trending.set.type_name(), "TrendingSet");
+        assert_eq!(trending.style, None);
+        match trending.set {
+            ContainerSet::TrendingSet(set) => {
+                assert!(set.set_id.is_none());
+                assert_eq!(set.meta.unwrap().hits, 30);
+            }
+            other => panic!("Expected a TrendingSet, got {:?}", other),
+        }
+
+        // Types we don't know don't fail the whole document
+        let unknown = container(r#"{"set": {"type": "BecauseYouSet", "whatever": 1}}"#);
+        assert!(matches!(unknown.set, ContainerSet::Unknown));
+    }
+
+    #[test]
+    fn test_ref_set_is_keyed_by_type() {
+        let doc: SetDocument = serde_json::from_str(&format!(
+            r#"{{"data": {{"PersonalizedCuratedSet": {{"setId": "xyz", "text": {}, "items": []}}}}}}"#,
+            TEXT
+        ))
+        .unwrap();
+        assert_eq!(doc.data.type_name(), "PersonalizedCuratedSet");
+        assert_eq!(doc.data.page_id("abc"), "xyz");
+        assert_eq!(doc.data.into_set().set_id.as_deref(), Some("xyz"));
+    }
+
+    #[test]
+    fn test_item_optional_fields() {
+        let item: Item = serde_json::from_str(
+            r#"{"text": {"title": {"full": {"program": {"default": {"content": "Clouds"}}}}},
+                "image": {"tile": {"1.78": {"program": {"default": {"url": "https://example.com/a.jpg"}}}}},
+                "unmodeled": [1, 2, 3]}"#,
+        )
+        .unwrap();
+        assert_eq!(item.item_type, None);
+        assert!(item.ratings.is_empty());
+        assert!(item.releases.is_empty());
+        assert!(item.text.description.is_none());
+        assert_eq!(
+            item.image.tile["1.78"]["program"]["default"],
+            ImageContent {
+                url: "https://example.com/a.jpg".to_string(),
+                master_width: None,
+            }
+        );
+        // Images are required
+        let missing = serde_json::from_str::<Item>(r#"{"text": {"title": {"full": {}}}}"#);
+        assert!(missing.is_err());
+    }
+}
@@ -1,216 +1,1203 @@
-use serde_json::Value;
-
-// Ideally, the parser would be able to parse the json into a strongly-typed
-// rust data structure that would cover all the different cases. Even better if
-// the definition for the data structure came from a library shared by both the client
-// and the server implementation.
-// As it is, this parser was optimized for fast development time. It handles the bare minimum
-// of cases required to get some images (of 1 specific aspect ratio) onto the screen.
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::content_source::{concurrency_limit, ContentSource};
+use crate::json_model::*;
+use crate::variants::{resolve, VariantField, VariantIssue, VariantPrecedence};
+
+// The json is deserialized into the strongly-typed model in json_model, so any
+// change in the schema shows up as a deserialization error naming the failing field.
+// The model only covers what's required to get some images onto the screen. Even better would be
+// if the definition for the data structure came from a library shared by both the client and the
+// server implementation.
 pub struct JsonParser {
-    main: Value,
+    source: DocumentSource,
+    // Shared with the download loop so both use the same connection pool
+    client: Client,
+    // Most set json fetched at the same time by load_all_rows
+    concurrency: usize,
+    precedence: VariantPrecedence,
+    // Print every VariantIssue as tiles are parsed
+    report_variants: bool,
+    // Language tag text is picked for, "default" text is used when it's None or missing
+    locale: Option<String>,
+    main: Option<HomeDocument>,
+    // Original image url to local file:// url, filled from a snapshot manifest
+    local_images: HashMap<String, String>,
+    // Whether to read the snapshot manifest at all
+    use_local_images: bool,
+}
+
+/// Where the parser reads the home and set json from.
+#[derive(Debug, Clone)]
+pub enum DocumentSource {
+    /// Download from a content server
+    Http(Endpoints),
+    /// Read from a local snapshot directory holding `home.json` and `sets/<refId>.json`
+    Directory(PathBuf),
+    /// Read the home json from a single local file, with any sets laid out next to it
+    /// like in a Directory
+    File(PathBuf),
+}
+
+impl Default for DocumentSource {
+    fn default() -> Self {
+        DocumentSource::Http(Endpoints::default())
+    }
+}
+
+/// Written next to home.json by the record command.
+/// Maps the original image urls to files relative to the snapshot directory,
+/// so a replay doesn't need the network for images either.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub images: BTreeMap<String, String>,
+}
+
+pub const MANIFEST_PATH: &str = "manifest.json";
+
+impl DocumentSource {
+    /// Url or file path of the home json
+    pub fn home_location(&self) -> String {
+        match self {
+            DocumentSource::Http(endpoints) => endpoints.home_url(),
+            DocumentSource::Directory(dir) => dir.join(HOME_PATH).display().to_string(),
+            DocumentSource::File(path) => path.display().to_string(),
+        }
+    }
+
+    /// Url or file path of the json for a referenced set
+    pub fn set_location(&self, ref_id: &str) -> String {
+        match self {
+            DocumentSource::Http(endpoints) => endpoints.set_url(ref_id),
+            DocumentSource::Directory(dir) => local_set_location(dir, ref_id),
+            DocumentSource::File(path) => local_set_location(parent_dir(path), ref_id),
+        }
+    }
+
+    /// Url or file path of the json for a later page of a set, starting at item offset
+    pub fn page_location(&self, set_id: &str, offset: usize) -> String {
+        match self {
+            DocumentSource::Http(endpoints) => endpoints.page_url(set_id, offset),
+            DocumentSource::Directory(dir) => local_page_location(dir, set_id, offset),
+            DocumentSource::File(path) => local_page_location(parent_dir(path), set_id, offset),
+        }
+    }
+
+    /// Reads the raw document at a location returned by home_location, set_location or page_location
+    pub async fn read(&self, client: &Client, location: &str) -> Result<String, ParseError> {
+        match self {
+            DocumentSource::Http(_) => fetch_text(client, location).await,
+            DocumentSource::Directory(_) | DocumentSource::File(_) => {
+                read_file(Path::new(location)).await
+            }
+        }
+    }
+}
+
+pub(crate) fn parent_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+fn local_set_location(dir: &Path, ref_id: &str) -> String {
+    dir.join("sets")
+        .join(format!("{}.json", ref_id))
+        .display()
+        .to_string()
+}
+
+fn local_page_location(dir: &Path, set_id: &str, offset: usize) -> String {
+    dir.join("sets")
+        .join(set_id)
+        .join(format!("{}.json", offset))
+        .display()
+        .to_string()
+}
+
+/// Where the home and set json are downloaded from.
+/// Can be loaded from a json config file, any missing fields use the defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Endpoints {
+    pub base_url: String,
+    /// Path of the home json relative to base_url
+    pub home_path: String,
+    /// Path of a set relative to base_url. `{}` is replaced by the set's refId
+    pub set_path: String,
+    /// Path of a later page of a set relative to base_url.
+    /// `{}` is replaced by the setId and `{offset}` by the index of the page's first item
+    pub page_path: String,
 }
 
+const DEFAULT_BASE_URL: &str = "https://cd-static.bamgrid.com/dp-117731241344/";
+const DEFAULT_SET_PATH: &str = "sets/{}.json";
+const DEFAULT_PAGE_PATH: &str = "sets/{}/{offset}.json";
+const HOME_PATH: &str = "home.json";
+const DEFAULT_CONCURRENCY: usize = 4;
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            home_path: HOME_PATH.to_string(),
+            set_path: DEFAULT_SET_PATH.to_string(),
+            page_path: DEFAULT_PAGE_PATH.to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    pub fn from_config_file(path: &Path) -> Result<Self, ParseError> {
+        let text = std::fs::read_to_string(path).map_err(|error| ParseError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        decode(&path.display().to_string(), &text)
+    }
+
+    pub fn home_url(&self) -> String {
+        self.join(&self.home_path)
+    }
+
+    pub fn set_url(&self, ref_id: &str) -> String {
+        self.join(&self.set_path.replace("{}", ref_id))
+    }
+
+    pub fn page_url(&self, set_id: &str, offset: usize) -> String {
+        self.join(
+            &self
+                .page_path
+                .replace("{offset}", &offset.to_string())
+                .replace("{}", set_id),
+        )
+    }
+
+    /// Splits the url of a home json into the base url and the home path, e.g.
+    /// `https://example.com/catalog/home.json?v=2` into `https://example.com/catalog/` and `home.json?v=2`
+    pub fn split_home_url(home_url: &str) -> Result<(String, String), ParseError> {
+        let invalid = |reason: String| ParseError::InvalidUrl {
+            url: home_url.to_string(),
+            reason,
+        };
+        let mut base = Url::parse(home_url).map_err(|e| invalid(e.to_string()))?;
+        let path = base.path().to_string();
+        let file = path.rsplit('/').next().unwrap_or_default();
+        if file.is_empty() {
+            return Err(invalid("the path doesn't name a home json".to_string()));
+        }
+        let home_path = match base.query() {
+            Some(query) => format!("{}?{}", file, query),
+            None => file.to_string(),
+        };
+        base.set_path(&path[..path.len() - file.len()]);
+        base.set_query(None);
+        base.set_fragment(None);
+        Ok((base.to_string(), home_path))
+    }
+
+    fn join(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+}
+
+/// Everything that can go wrong while loading the catalog
 #[derive(Debug)]
+pub enum ParseError {
+    /// The request failed before we got a response, or the body could not be read
+    Transport { url: String, error: reqwest::Error },
+    /// The server responded, but not with a success status
+    Status {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+    /// A local file could not be read
+    Io { path: PathBuf, error: io::Error },
+    /// The document is not valid json
+    Decode {
+        location: String,
+        error: serde_json::Error,
+    },
+    /// The document is valid json, but does not match the expected schema.
+    /// The error includes the path of the failing field.
+    Schema {
+        location: String,
+        error: serde_path_to_error::Error<serde_json::Error>,
+    },
+    /// A yaml document is not valid yaml, or does not match the expected schema
+    Yaml {
+        location: String,
+        error: serde_yaml::Error,
+    },
+    /// A url given on the command line can't be used
+    InvalidUrl { url: String, reason: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Transport { url, error } => write!(f, "could not fetch {}: {}", url, error),
+            ParseError::Status { url, status } => write!(f, "{} returned {}", url, status),
+            ParseError::Io { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            ParseError::Decode { location, error } => {
+                write!(f, "{} is not valid json: {}", location, error)
+            }
+            ParseError::Schema { location, error } => {
+                write!(f, "unexpected schema in {}: {}", location, error)
+            }
+            ParseError::Yaml { location, error } => {
+                write!(f, "could not parse {}: {}", location, error)
+            }
+            ParseError::InvalidUrl { url, reason } => write!(f, "invalid url {}: {}", url, reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Transport { error, .. } => Some(error),
+            ParseError::Status { .. } => None,
+            ParseError::Io { error, .. } => Some(error),
+            ParseError::Decode { error, .. } => Some(error),
+            ParseError::Schema { error, .. } => Some(error),
+            ParseError::Yaml { error, .. } => Some(error),
+            ParseError::InvalidUrl { .. } => None,
+        }
+    }
+}
+
+pub(crate) async fn fetch_text(client: &Client, url: &str) -> Result<String, ParseError> {
+    let transport_error = |error| ParseError::Transport {
+        url: url.to_string(),
+        error,
+    };
+    let resp = client.get(url).send().await.map_err(transport_error)?;
+    if !resp.status().is_success() {
+        return Err(ParseError::Status {
+            url: url.to_string(),
+            status: resp.status(),
+        });
+    }
+    resp.text().await.map_err(transport_error)
+}
+
+pub(crate) async fn read_file(path: &Path) -> Result<String, ParseError> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(|error| ParseError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+}
+
+// Deserializes through serde_path_to_error so schema errors include
+// the path of the field that failed, e.g. `data.StandardCollection.containers[3].set.items`
+// Location is only used for error messages.
+pub(crate) fn decode<T: DeserializeOwned>(location: &str, text: &str) -> Result<T, ParseError> {
+    let de = &mut serde_json::Deserializer::from_str(text);
+    serde_path_to_error::deserialize(de).map_err(|error| {
+        // Data errors mean the json was well formed, but didn't match our model
+        if error.inner().is_data() {
+            ParseError::Schema {
+                location: location.to_string(),
+                error,
+            }
+        } else {
+            ParseError::Decode {
+                location: location.to_string(),
+                error: error.into_inner(),
+            }
+        }
+    })
+}
+
+#[derive(Debug, Default)]
 pub struct TileInfo {
     pub title: String,
+    /// The 1.78 image, or empty if the item doesn't have one
     pub img_url: String,
+    /// Every aspect ratio the item has an image for, sorted by aspect ratio
+    pub images: Vec<TileImage>,
+    pub content_type: ContentType,
+    pub content_id: Option<String>,
+    /// Stable id of a program across its versions, used in deep links
+    pub family_id: Option<String>,
+    /// Id of a series used in deep links
+    pub series_id: Option<String>,
+    pub collection_id: Option<String>,
+    /// e.g. "TV-PG"
+    pub rating: Option<String>,
+    pub release_year: Option<u32>,
+    pub brief_description: Option<String>,
+    pub full_description: Option<String>,
+    /// Conflicting variants and fallbacks hit while picking the title, images and descriptions
+    pub variant_issues: Vec<VariantIssue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentType {
+    Series,
+    /// A movie, episode or short
+    Program,
+    Collection,
+    /// Holds the raw type, if there was one
+    Unknown(Option<String>),
+}
+
+impl Default for ContentType {
+    fn default() -> Self {
+        ContentType::Unknown(None)
+    }
+}
+
+impl ContentType {
+    fn from_item_type(item_type: &Option<String>) -> Self {
+        match item_type.as_deref() {
+            Some("DmcSeries") => ContentType::Series,
+            Some("DmcVideo") => ContentType::Program,
+            Some("StandardCollection") => ContentType::Collection,
+            other => ContentType::Unknown(other.map(str::to_string)),
+        }
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentType::Series => write!(f, "series"),
+            ContentType::Program => write!(f, "program"),
+            ContentType::Collection => write!(f, "collection"),
+            ContentType::Unknown(Some(raw)) => write!(f, "{}", raw),
+            ContentType::Unknown(None) => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TileImage {
+    /// Width over height, e.g. 1.78 for landscape or 0.71 for posters
+    pub aspect_ratio: f32,
+    pub url: String,
+    /// Width of the source image, if known
+    pub master_width: Option<u32>,
+}
+
+impl TileInfo {
+    /// Url of the image with the aspect ratio closest to the requested one.
+    /// Falls back to img_url if there are no images.
+    pub fn image_url_for(&self, aspect_ratio: f32) -> &str {
+        // Compare ratios on a log scale, so 0.5 and 2.0 are the same distance from 1.0
+        let distance = |image: &TileImage| (image.aspect_ratio / aspect_ratio).ln().abs();
+        self.images
+            .iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .map_or(&self.img_url, |image| &image.url)
+    }
+}
+
+/// How a row is displayed, from the style of its container on the home page
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RowStyle {
+    #[default]
+    Standard,
+    /// Large banners, one at a time
+    Hero,
+    /// Studio and franchise tiles, e.g. Disney, Pixar and Marvel
+    Brand,
+    Character,
+    /// Holds the raw style
+    Other(String),
+}
+
+impl RowStyle {
+    pub fn from_container_style(style: &Option<String>) -> Self {
+        match style.as_deref() {
+            None | Some("standard") => RowStyle::Standard,
+            Some("hero") => RowStyle::Hero,
+            Some("brand") => RowStyle::Brand,
+            Some("character") => RowStyle::Character,
+            Some(other) => RowStyle::Other(other.to_string()),
+        }
+    }
+
+    /// The style as it appears in the json
+    pub fn name(&self) -> &str {
+        match self {
+            RowStyle::Standard => "standard",
+            RowStyle::Hero => "hero",
+            RowStyle::Brand => "brand",
+            RowStyle::Character => "character",
+            RowStyle::Other(style) => style,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct RowInfo {
     pub title: String,
+    pub style: RowStyle,
     pub tiles: Vec<TileInfo>,
+    /// refId of the set holding this row's tiles, if they haven't been fetched yet
+    pub pending_ref: Option<String>,
+    /// setId used to fetch further pages, if the set has one
+    pub set_id: Option<String>,
+    /// Number of tiles in the whole set. More than tiles.len() if there are pages left to fetch.
+    pub total: usize,
+}
+
+impl RowInfo {
+    /// Whether there are tiles left on later pages
+    pub fn has_more_pages(&self) -> bool {
+        self.set_id.is_some() && self.tiles.len() < self.total
+    }
+
+    /// Adds the tiles of a page fetched for this row
+    pub fn append_page(&mut self, page: SetPage) {
+        self.tiles.extend(page.tiles);
+        self.set_id = page.set_id;
+        self.total = page.total.max(self.tiles.len());
+    }
 }
 
-const MAIN_URL: &str = "https://cd-static.bamgrid.com/dp-117731241344/home.json";
+/// Tiles from one page of a set
+#[derive(Debug, Default)]
+pub struct SetPage {
+    pub tiles: Vec<TileInfo>,
+    pub set_id: Option<String>,
+    /// Number of tiles in the whole set, across every page
+    pub total: usize,
+}
+
+impl SetPage {
+    /// Whether a page that was expected to hold tiles came back empty, because the set is shorter
+    /// than its total said. Paging has to stop there, or the same page would be requested forever.
+    pub fn ends_set_early(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+impl Default for JsonParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl JsonParser {
     pub fn new() -> Self {
-        JsonParser { main: Value::Null }
+        Self::with_source(DocumentSource::default())
     }
 
-    fn parse_main_json(&mut self) {
-        self.main = reqwest::blocking::get(MAIN_URL).unwrap().json().unwrap();
+    pub fn with_endpoints(endpoints: Endpoints) -> Self {
+        Self::with_source(DocumentSource::Http(endpoints))
     }
 
-    fn parse_ref_json(ref_id: &str) -> Value {
-        let url = format!(
-            "https://cd-static.bamgrid.com/dp-117731241344/sets/{}.json",
-            ref_id
-        );
-        reqwest::blocking::get(url).unwrap().json().unwrap()
+    pub fn with_source(source: DocumentSource) -> Self {
+        JsonParser {
+            source,
+            client: Client::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+            precedence: VariantPrecedence::default(),
+            report_variants: false,
+            locale: None,
+            main: None,
+            local_images: HashMap::new(),
+            use_local_images: true,
+        }
     }
 
-    pub fn parse_all_rows(&mut self) -> Vec<RowInfo> {
-        self.parse_main_json();
-        let mut rows = Vec::new();
+    pub fn set_client(&mut self, client: Client) {
+        self.client = client;
+    }
 
-        let mut i = 0;
-        while !matches!(self.get_container(i), Value::Null) {
-            let mut container = self.get_container(i);
-            let mut row_info = RowInfo {
-                title: Self::get_container_title(container),
-                tiles: Vec::new(),
-            };
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency_limit(concurrency);
+    }
+
+    pub fn set_variant_precedence(&mut self, precedence: VariantPrecedence) {
+        self.precedence = precedence;
+    }
+
+    pub fn set_report_variants(&mut self, report_variants: bool) {
+        self.report_variants = report_variants;
+    }
+
+    pub fn set_locale(&mut self, locale: Option<String>) {
+        self.locale = locale;
+    }
+
+    /// Whether tiles from a recorded snapshot point at its local copies of the images.
+    /// Turned off when the original urls matter, e.g. when comparing a snapshot with the live catalog
+    pub fn set_use_local_images(&mut self, use_local_images: bool) {
+        self.use_local_images = use_local_images;
+    }
+
+    async fn fetch_json<T: DeserializeOwned>(&self, location: &str) -> Result<T, ParseError> {
+        let text = self.source.read(&self.client, location).await?;
+        decode(location, &text)
+    }
+
+    pub fn source(&self) -> &DocumentSource {
+        &self.source
+    }
+
+    pub(crate) async fn parse_main_json(&mut self) -> Result<(), ParseError> {
+        self.main = Some(self.fetch_json(&self.source.home_location()).await?);
+        if let (DocumentSource::Directory(dir), true) = (&self.source, self.use_local_images) {
+            self.local_images = Self::parse_manifest(dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads the containers of the home json without decoding them,
+    /// so one container that doesn't match the model can't hide the others
+    pub(crate) async fn parse_raw_containers(&self) -> Result<Vec<serde_json::Value>, ParseError> {
+        let home: HomeDocument<serde_json::Value> =
+            self.fetch_json(&self.source.home_location()).await?;
+        Ok(home.data.standard_collection.containers)
+    }
+
+    // Snapshots without a manifest are fine, images are then downloaded as usual
+    async fn parse_manifest(dir: &Path) -> Result<HashMap<String, String>, ParseError> {
+        let path = dir.join(MANIFEST_PATH);
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        let text = read_file(&path).await?;
+        let manifest: SnapshotManifest = decode(&path.display().to_string(), &text)?;
+        // The download loop needs absolute urls
+        let dir = dir.canonicalize().map_err(|error| ParseError::Io {
+            path: dir.to_path_buf(),
+            error,
+        })?;
+        let mut local_images = HashMap::new();
+        for (url, file) in manifest.images {
+            if let Ok(local_url) = Url::from_file_path(dir.join(file)) {
+                local_images.insert(url, local_url.to_string());
+            }
+        }
+        Ok(local_images)
+    }
+
+    pub(crate) async fn parse_ref_set(&self, ref_id: &str) -> Result<RefSet, ParseError> {
+        let doc: SetDocument = self.fetch_json(&self.source.set_location(ref_id)).await?;
+        Ok(doc.data)
+    }
+
+    pub(crate) async fn parse_page_json(
+        &self,
+        set_id: &str,
+        offset: usize,
+    ) -> Result<CuratedSet, ParseError> {
+        let location = self.source.page_location(set_id, offset);
+        let doc: SetDocument = self.fetch_json(&location).await?;
+        Ok(doc.data.into_set())
+    }
+
+    /// Parses the home json into rows.
+    /// Rows backed by a referenced set are returned as placeholders with no tiles
+    /// and pending_ref set, so the set can be fetched later with parse_ref_tiles.
+    pub async fn parse_main_rows(&mut self) -> Result<Vec<RowInfo>, ParseError> {
+        self.parse_main_json().await?;
+        let mut rows = Vec::new();
 
-            let ref_container;
-            if let Value::String(ref_id) = &container["refId"] {
-                ref_container = Self::parse_ref_json(ref_id);
-                for val in &["CuratedSet", "TrendingSet", "PersonalizedCuratedSet"] {
-                    if !matches!(&ref_container["data"][val], Value::Null) {
-                        container = &ref_container["data"][val];
+        for (i, container) in self.containers().iter().enumerate() {
+            // The title on the home page is used even for referenced sets
+            let title = self.get_container_title(container);
+            let row_info = match &container.set {
+                ContainerSet::CuratedSet(set)
+                | ContainerSet::TrendingSet(set)
+                | ContainerSet::PersonalizedCuratedSet(set) => {
+                    let page = self.get_set_page(set);
+                    RowInfo {
+                        title,
+                        style: RowStyle::from_container_style(&container.style),
+                        tiles: page.tiles,
+                        pending_ref: None,
+                        set_id: page.set_id,
+                        total: page.total,
                     }
                 }
+                ContainerSet::SetRef(set_ref) => RowInfo {
+                    title,
+                    style: RowStyle::from_container_style(&container.style),
+                    tiles: Vec::new(),
+                    pending_ref: Some(set_ref.ref_id.clone()),
+                    set_id: None,
+                    total: 0,
+                },
+                ContainerSet::Unknown => {
+                    eprintln!("Unknown set type in row {}", i);
+                    continue;
+                }
+            };
+            if !row_info.tiles.is_empty() || row_info.pending_ref.is_some() {
+                rows.push(row_info);
+            } else {
+                eprintln!("Could not parse tiles from row {}", i);
             }
+        }
+
+        Ok(rows)
+    }
+
+    /// Fetches the first page of tiles for a placeholder row returned by parse_main_rows
+    pub async fn parse_ref_page(&self, ref_id: &str) -> Result<SetPage, ParseError> {
+        let ref_set = self.parse_ref_set(ref_id).await?;
+        let set_id = ref_set.page_id(ref_id).to_string();
+        let mut page = self.get_set_page(&ref_set.into_set());
+        page.set_id = Some(set_id);
+        Ok(page)
+    }
 
-            let mut j = 0;
-            while !matches!(Self::get_container_item(container, j), Value::Null) {
-                let item = Self::get_container_item(container, j);
-                let tile_info = TileInfo {
-                    title: Self::get_item_title(item).unwrap_or_else(|| "".to_string()),
-                    img_url: Self::get_item_image_url(item).unwrap_or_else(|| "".to_string()),
-                };
-                row_info.tiles.push(tile_info);
-                j += 1;
+    /// Fetches the page of a set starting at item offset
+    pub async fn parse_page(&self, set_id: &str, offset: usize) -> Result<SetPage, ParseError> {
+        let set = self.parse_page_json(set_id, offset).await?;
+        Ok(self.get_set_page(&set))
+    }
+
+    /// Parses the home json and fetches every referenced set, and every page of every set, up front.
+    /// Same as ContentSource::load_all_rows, for callers that only deal with the bamgrid catalog.
+    pub async fn parse_all_rows(&mut self) -> Result<Vec<RowInfo>, ParseError> {
+        self.load_all_rows().await
+    }
+
+    fn get_set_page(&self, set: &CuratedSet) -> SetPage {
+        let tiles = self.get_tile_infos(set);
+        // Sets without paging info are complete
+        let total = match &set.meta {
+            Some(meta) => meta.hits.max(meta.offset + tiles.len()),
+            None => tiles.len(),
+        };
+        SetPage {
+            tiles,
+            set_id: set.set_id.clone(),
+            total,
+        }
+    }
+
+    fn get_tile_infos(&self, set: &CuratedSet) -> Vec<TileInfo> {
+        let tiles: Vec<TileInfo> = set
+            .items
+            .iter()
+            .map(|item| self.localize(self.get_tile_info(item)))
+            .collect();
+        if self.report_variants {
+            let set_title = self.localized_text(&set.text.title.full.set).unwrap_or("");
+            for (i, tile) in tiles.iter().enumerate() {
+                for issue in &tile.variant_issues {
+                    eprintln!("\"{}\" item {} \"{}\": {}", set_title, i, tile.title, issue);
+                }
             }
-            if !row_info.tiles.is_empty() {
-                rows.push(row_info);
-            } else {
-                println!("Could not parse tiles from row {}", i);
+        }
+        tiles
+    }
+
+    // Points the tile at the recorded copies of its images, if there are any
+    fn localize(&self, mut tile: TileInfo) -> TileInfo {
+        if let Some(local_url) = self.local_images.get(&tile.img_url) {
+            tile.img_url = local_url.clone();
+        }
+        for image in &mut tile.images {
+            if let Some(local_url) = self.local_images.get(&image.url) {
+                image.url = local_url.clone();
             }
-            i += 1;
         }
+        tile
+    }
+
+    pub fn containers(&self) -> &[Container] {
+        match &self.main {
+            Some(main) => &main.data.standard_collection.containers,
+            None => &[],
+        }
+    }
 
-        rows
+    pub fn get_container_title(&self, container: &Container) -> String {
+        let text = match &container.set {
+            ContainerSet::CuratedSet(set)
+            | ContainerSet::TrendingSet(set)
+            | ContainerSet::PersonalizedCuratedSet(set) => &set.text,
+            ContainerSet::SetRef(set_ref) => &set_ref.text,
+            ContainerSet::Unknown => return "".to_string(),
+        };
+        self.localized_text(&text.title.full.set)
+            .unwrap_or_default()
+            .to_string()
     }
 
-    pub fn get_container(&self, idx: usize) -> &Value {
-        &self.main["data"]["StandardCollection"]["containers"][idx]["set"]
+    // Picks the text for the locale, e.g. "fr-CA", then its language "fr", then "default"
+    fn localized_text<'a>(&self, text: &'a LocalizedText) -> Option<&'a str> {
+        let locale = self.locale.as_deref();
+        let language = locale.and_then(|locale| locale.split(['-', '_']).next());
+        [locale, language, Some("default")]
+            .iter()
+            .flatten()
+            .find_map(|key| text.get(*key))
+            .map(|text| text.content.as_str())
     }
 
-    pub fn get_container_title(container: &Value) -> String {
-        if let Value::String(string) =
-            &container["text"]["title"]["full"]["set"]["default"]["content"]
-        {
-            string.clone()
-        } else {
-            "".to_string()
+    pub fn get_tile_info(&self, item: &Item) -> TileInfo {
+        let mut issues = Vec::new();
+        TileInfo {
+            title: self
+                .resolve_item_title(item, &mut issues)
+                .unwrap_or_default(),
+            img_url: self
+                .resolve_item_image_url(item, &mut issues)
+                .unwrap_or_default(),
+            images: self.resolve_item_images(item, &mut issues),
+            content_type: ContentType::from_item_type(&item.item_type),
+            content_id: item.content_id.clone(),
+            family_id: item.family_id.clone(),
+            series_id: item.encoded_series_id.clone(),
+            collection_id: item.collection_id.clone(),
+            rating: item.ratings.first().map(|rating| rating.value.clone()),
+            release_year: item
+                .releases
+                .iter()
+                .find_map(|release| release.release_year),
+            brief_description: self.resolve_item_description(
+                item,
+                VariantField::BriefDescription,
+                &mut issues,
+            ),
+            full_description: self.resolve_item_description(
+                item,
+                VariantField::FullDescription,
+                &mut issues,
+            ),
+            variant_issues: issues,
         }
     }
 
-    pub fn get_container_item(container: &Value, idx: usize) -> &Value {
-        &container["items"][idx]
+    pub fn get_item_title(&self, item: &Item) -> Option<String> {
+        self.resolve_item_title(item, &mut Vec::new())
+    }
+
+    pub fn get_item_image_url(&self, item: &Item) -> Option<String> {
+        self.resolve_item_image_url(item, &mut Vec::new())
+    }
+
+    fn resolve_item_title(&self, item: &Item, issues: &mut Vec<VariantIssue>) -> Option<String> {
+        self.resolve_text(&item.text.title.full, VariantField::Title, true, issues)
+    }
+
+    fn resolve_item_description(
+        &self,
+        item: &Item,
+        field: VariantField,
+        issues: &mut Vec<VariantIssue>,
+    ) -> Option<String> {
+        let description = item.text.description.as_ref()?;
+        let variants = match field {
+            VariantField::BriefDescription => &description.brief,
+            _ => &description.full,
+        };
+        // Descriptions are optional, so only conflicts are worth reporting
+        self.resolve_text(variants.as_ref()?, field, false, issues)
+    }
+
+    fn resolve_text(
+        &self,
+        variants: &TextVariants,
+        field: VariantField,
+        report_missing: bool,
+        issues: &mut Vec<VariantIssue>,
+    ) -> Option<String> {
+        resolve(
+            variants,
+            &self.precedence.text,
+            |variant| self.localized_text(variant).map(str::to_string),
+            field,
+            report_missing,
+            issues,
+        )
     }
 
-    pub fn get_item_title(item: &Value) -> Option<String> {
-        let prefix = &item["text"]["title"]["full"];
-        let mut variable = &Value::Null;
-        // May be more possible options here
-        for val in &["series", "program", "collection"] {
-            match prefix[val] {
-                Value::Null => continue,
-                _ => variable = &prefix[val],
+    fn resolve_item_image_url(
+        &self,
+        item: &Item,
+        issues: &mut Vec<VariantIssue>,
+    ) -> Option<String> {
+        let field = VariantField::Image("1.78".to_string());
+        let variants = match item.image.tile.get("1.78") {
+            Some(variants) => variants,
+            None => {
+                issues.push(VariantIssue::Missing {
+                    field,
+                    available: Vec::new(),
+                });
+                return None;
             }
+        };
+        self.resolve_image(variants, field, true, issues)
+            .map(|image| image.url.clone())
+    }
+
+    fn resolve_item_images(&self, item: &Item, issues: &mut Vec<VariantIssue>) -> Vec<TileImage> {
+        let mut images: Vec<TileImage> = item
+            .image
+            .tile
+            .iter()
+            .filter_map(|(ratio, variants)| {
+                // Keys are aspect ratios like "1.78", skip anything else
+                let aspect_ratio = ratio.parse::<f32>().ok().filter(|r| *r > 0.0)?;
+                // The 1.78 image was already checked when resolving img_url
+                let mut ratio_issues = Vec::new();
+                let image = self.resolve_image(
+                    variants,
+                    VariantField::Image(ratio.clone()),
+                    false,
+                    &mut ratio_issues,
+                )?;
+                if ratio != "1.78" {
+                    issues.append(&mut ratio_issues);
+                }
+                Some(TileImage {
+                    aspect_ratio,
+                    url: image.url.clone(),
+                    master_width: image.master_width,
+                })
+            })
+            .collect();
+        images.sort_by(|a, b| a.aspect_ratio.total_cmp(&b.aspect_ratio));
+        images
+    }
+
+    fn resolve_image<'a>(
+        &self,
+        variants: &'a ImageVariants,
+        field: VariantField,
+        report_missing: bool,
+        issues: &mut Vec<VariantIssue>,
+    ) -> Option<&'a ImageContent> {
+        resolve(
+            variants,
+            &self.precedence.image,
+            |variant| variant.get("default"),
+            field,
+            report_missing,
+            issues,
+        )
+    }
+}
+
+#[async_trait]
+impl ContentSource for JsonParser {
+    async fn load_rows(&mut self) -> Result<Vec<RowInfo>, ParseError> {
+        self.parse_main_rows().await
+    }
+
+    async fn load_ref_page(&self, ref_id: &str) -> Result<SetPage, ParseError> {
+        self.parse_ref_page(ref_id).await
+    }
+
+    async fn load_page(&self, set_id: &str, offset: usize) -> Result<SetPage, ParseError> {
+        self.parse_page(set_id, offset).await
+    }
+
+    fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Deterministic snapshot checked into the repo, see fixtures/snapshot
+    fn fixture_parser() -> JsonParser {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/snapshot");
+        JsonParser::with_source(DocumentSource::Directory(dir))
+    }
+
+    // Test main parser can run without crashing
+    #[tokio::test]
+    async fn test_parse() {
+        let mut parser = fixture_parser();
+        parser.parse_main_json().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_container_parsing() {
+        let mut parser = fixture_parser();
+        parser.parse_main_json().await.unwrap();
+        assert!(matches!(
+            parser.containers()[0].set,
+            ContainerSet::CuratedSet(_)
+        ));
+        assert!(matches!(
+            parser.containers()[1].set,
+            ContainerSet::SetRef(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_title_parsing() {
+        let mut parser = fixture_parser();
+        parser.parse_main_json().await.unwrap();
+        let title = parser.get_container_title(&parser.containers()[0]);
+        assert_eq!(title, "New to Disney+");
+    }
+
+    #[tokio::test]
+    async fn test_locale() {
+        let mut parser = fixture_parser();
+        parser.set_locale(Some("fr-CA".to_string()));
+        let rows = parser.parse_all_rows().await.unwrap();
+        assert_eq!(rows[0].title, "Nouveau sur Disney+");
+        assert_eq!(rows[0].tiles[0].title, "L'Étoffe des héros");
+        // No french text, falls back to default
+        assert_eq!(rows[0].tiles[1].title, "Clouds");
+
+        parser.set_locale(Some("he".to_string()));
+        let rows = parser.parse_all_rows().await.unwrap();
+        assert_eq!(rows[0].title, "New to Disney+");
+        assert_eq!(rows[0].tiles[0].title, "החומר הנכון");
+    }
+
+    #[tokio::test]
+    async fn test_item_parsing() {
+        let mut parser = fixture_parser();
+        parser.parse_main_json().await.unwrap();
+        let item = match &parser.containers()[0].set {
+            ContainerSet::CuratedSet(set) => &set.items[0],
+            _ => panic!("Expected a CuratedSet"),
+        };
+        let title = parser.get_item_title(item).unwrap();
+        assert_eq!(title, "The Right Stuff");
+        let url = parser.get_item_image_url(item).unwrap();
+        assert_eq!(url, "https://prod-ripcut-delivery.disney-plus.net/v1/variant/disney/3C33485A3043C22B8C89E131693E8B5B9306DAA4E48612A655560752977728A6/scale?format=jpeg&quality=90&scalingAlgorithm=lanczos3&width=500");
+    }
+
+    #[tokio::test]
+    async fn test_aspect_ratios() {
+        let mut parser = fixture_parser();
+        let rows = parser.parse_all_rows().await.unwrap();
+        let tile = &rows[0].tiles[0];
+        let ratios: Vec<_> = tile.images.iter().map(|i| i.aspect_ratio).collect();
+        assert_eq!(ratios, [0.71, 1.78]);
+        assert_eq!(tile.images[0].master_width, Some(1000));
+        assert_eq!(tile.image_url_for(0.67), tile.images[0].url);
+        assert_eq!(tile.image_url_for(3.91), tile.img_url);
+        // Only has a 1.78 image, so that's the best match for posters too
+        let tile = &rows[0].tiles[1];
+        assert_eq!(tile.image_url_for(0.71), tile.img_url);
+    }
+
+    #[tokio::test]
+    async fn test_item_metadata() {
+        let mut parser = fixture_parser();
+        let rows = parser.parse_all_rows().await.unwrap();
+        let series = &rows[0].tiles[0];
+        assert_eq!(series.content_type, ContentType::Series);
+        assert_eq!(series.series_id.as_deref(), Some("3cKfHl0VNuE3"));
+        assert_eq!(series.rating.as_deref(), Some("TV-PG"));
+        assert_eq!(series.release_year, Some(2020));
+        assert_eq!(
+            series.brief_description.as_deref(),
+            Some("America's first astronauts race to space.")
+        );
+        assert!(series.full_description.is_some());
+
+        let program = &rows[0].tiles[1];
+        assert_eq!(program.content_type, ContentType::Program);
+        assert_eq!(program.family_id.as_deref(), Some("7xPz1yPGcKkR"));
+        assert_eq!(program.brief_description, None);
+
+        let collection = &rows[1].tiles[1];
+        assert_eq!(collection.content_type, ContentType::Collection);
+        assert_eq!(collection.rating, None);
+    }
+
+    #[tokio::test]
+    async fn test_variant_precedence() {
+        let mut parser = fixture_parser();
+        let rows = parser.parse_all_rows().await.unwrap();
+        assert!(rows[0].tiles[1].variant_issues.is_empty());
+
+        parser.set_variant_precedence(VariantPrecedence {
+            text: vec!["series".to_string()],
+            ..VariantPrecedence::default()
+        });
+        let rows = parser.parse_all_rows().await.unwrap();
+        assert_eq!(rows[0].tiles[0].title, "The Right Stuff");
+        let program = &rows[0].tiles[1];
+        assert_eq!(program.title, "");
+        assert_eq!(
+            program.variant_issues,
+            [VariantIssue::Missing {
+                field: VariantField::Title,
+                available: vec!["program".to_string()],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_container_iter() {
+        let mut parser = fixture_parser();
+        parser.parse_main_json().await.unwrap();
+        assert_eq!(parser.containers().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_parse_tiles() {
+        let mut parser = fixture_parser();
+        let tile_rows = parser.parse_all_rows().await.unwrap();
+        // The empty row is dropped
+        assert_eq!(tile_rows.len(), 2);
+        assert_eq!(tile_rows[0].tiles.len(), 2);
+        // Referenced set keeps the title from the home page
+        assert_eq!(tile_rows[1].title, "Trending");
+        let titles: Vec<_> = tile_rows[1].tiles.iter().map(|t| &t.title).collect();
+        assert_eq!(titles, ["The Mandalorian", "Star Wars", "Andor"]);
+        assert_eq!(tile_rows[1].total, 3);
+        assert!(!tile_rows[1].has_more_pages());
+    }
+
+    #[tokio::test]
+    async fn test_parse_main_rows_placeholders() {
+        let mut parser = fixture_parser();
+        let rows = parser.parse_main_rows().await.unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].pending_ref.is_none());
+        assert!(rows[1].tiles.is_empty());
+        let ref_id = rows[1].pending_ref.as_ref().unwrap();
+        let page = parser.parse_ref_page(ref_id).await.unwrap();
+        assert_eq!(page.tiles.len(), 2);
+        assert_eq!(page.total, 3);
+    }
+
+    #[tokio::test]
+    async fn test_parse_page() {
+        let mut parser = fixture_parser();
+        let mut rows = parser.parse_main_rows().await.unwrap();
+        // Inline sets with every item on the first page don't page
+        assert!(!rows[0].has_more_pages());
+
+        let ref_id = rows[1].pending_ref.take().unwrap();
+        rows[1].append_page(parser.parse_ref_page(&ref_id).await.unwrap());
+        assert!(rows[1].has_more_pages());
+        let set_id = rows[1].set_id.clone().unwrap();
+        let page = parser
+            .parse_page(&set_id, rows[1].tiles.len())
+            .await
+            .unwrap();
+        rows[1].append_page(page);
+        assert_eq!(rows[1].tiles[2].title, "Andor");
+        assert!(!rows[1].has_more_pages());
+    }
+
+    #[test]
+    fn test_page_url() {
+        let endpoints = Endpoints::default();
+        assert_eq!(
+            endpoints.page_url("abc", 15),
+            "https://cd-static.bamgrid.com/dp-117731241344/sets/abc/15.json"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_set_file() {
+        let parser = fixture_parser();
+        let err = parser.parse_ref_set("does-not-exist").await.unwrap_err();
+        assert!(matches!(err, ParseError::Io { .. }));
+    }
+
+    #[test]
+    fn test_split_home_url() {
+        let split = |url| Endpoints::split_home_url(url).unwrap();
+        assert_eq!(
+            split("https://example.com/catalog/home.json?v=2#top"),
+            (
+                "https://example.com/catalog/".to_string(),
+                "home.json?v=2".to_string()
+            )
+        );
+        assert_eq!(
+            split("http://localhost:8080/home.json"),
+            (
+                "http://localhost:8080/".to_string(),
+                "home.json".to_string()
+            )
+        );
+        // Nothing to use as the home json
+        for url in [
+            "https://example.com",
+            "https://example.com/catalog/",
+            "not a url",
+        ] {
+            assert!(matches!(
+                Endpoints::split_home_url(url),
+                Err(ParseError::InvalidUrl { .. })
+            ));
         }
-        if let Value::String(string) = &variable["default"]["content"] {
-            Some(string.clone())
-        } else {
-            None
+    }
+
+    #[test]
+    fn test_endpoints_from_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("endpoints.json");
+        std::fs::write(
+            &path,
+            r#"{"base_url": "https://example.com/catalog", "set_path": "ref/{}.json"}"#,
+        )
+        .unwrap();
+        let endpoints = Endpoints::from_config_file(&path).unwrap();
+        assert_eq!(
+            endpoints.home_url(),
+            "https://example.com/catalog/home.json"
+        );
+        assert_eq!(
+            endpoints.set_url("abc"),
+            "https://example.com/catalog/ref/abc.json"
+        );
+        // Missing fields keep their defaults
+        assert_eq!(
+            endpoints.page_url("abc", 15),
+            "https://example.com/catalog/sets/abc/15.json"
+        );
+
+        std::fs::write(&path, r#"{"base_url": 5}"#).unwrap();
+        match Endpoints::from_config_file(&path).unwrap_err() {
+            ParseError::Schema { error, .. } => assert_eq!(error.path().to_string(), "base_url"),
+            err => panic!("Expected a schema error, got {:?}", err),
         }
+        std::fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            Endpoints::from_config_file(&path),
+            Err(ParseError::Decode { .. })
+        ));
+        assert!(matches!(
+            Endpoints::from_config_file(&dir.path().join("missing.json")),
+            Err(ParseError::Io { .. })
+        ));
     }
 
-    pub fn get_item_image_url(item: &Value) -> Option<String> {
-        let prefix = &item["image"]["tile"]["1.78"];
-        let mut variable = &Value::Null;
-        for val in &["series", "program", "collection", "default"] {
-            match prefix[val] {
-                Value::Null => continue,
-                _ => variable = &prefix[val],
+    #[test]
+    fn test_schema_error_names_field() {
+        let json = r#"{"data": {"StandardCollection": {"containers": [{"set": {"type": "CuratedSet"}}]}}}"#;
+        let err = decode::<HomeDocument>("test", json).unwrap_err();
+        match err {
+            ParseError::Schema { error, .. } => {
+                assert_eq!(
+                    error.path().to_string(),
+                    "data.StandardCollection.containers[0].set"
+                );
             }
+            _ => panic!("Expected a schema error, got {:?}", err),
         }
-        if let Value::String(string) = &variable["default"]["url"] {
-            Some(string.clone())
-        } else {
-            None
-        }
-    }
-}
-
-// These tests are extremely fragile and based off of specific values in the json
-// on the date the tests were written. They were used to quickly confirm the parser's
-// correct behavior during development. They're left commented out here for historical perspective.
-
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-
-//     // Test main parser can run without crashing
-//     #[test]
-//     fn test_parse() {
-//         let mut parser = JsonParser::new();
-//         parser.parse_main_json();
-//     }
-
-//     #[test]
-//     fn test_container_parsing() {
-//         let mut parser = JsonParser::new();
-//         parser.parse_main_json();
-//         assert!(matches!(parser.get_container(0), Value::Object { .. }));
-//         assert!(matches!(parser.get_container(10000000), Value::Null));
-//     }
-
-//     #[test]
-//     fn test_title_parsing() {
-//         let mut parser = JsonParser::new();
-//         parser.parse_main_json();
-//         assert!(matches!(parser.get_container(0), Value::Object { .. }));
-//         let container = parser.get_container(0);
-//         let title = JsonParser::get_container_title(container);
-//         assert_eq!(title, "New to Disney+");
-//     }
-
-//     #[test]
-//     fn test_item_parsing() {
-//         let mut parser = JsonParser::new();
-//         parser.parse_main_json();
-//         let container = parser.get_container(0);
-//         let item = JsonParser::get_container_item(container, 0);
-//         let title = JsonParser::get_item_title(item).unwrap();
-//         assert_eq!(title, "The Right Stuff");
-//         let url = JsonParser::get_item_image_url(item).unwrap();
-//         assert_eq!(url, "https://prod-ripcut-delivery.disney-plus.net/v1/variant/disney/3C33485A3043C22B8C89E131693E8B5B9306DAA4E48612A655560752977728A6/scale?format=jpeg&quality=90&scalingAlgorithm=lanczos3&width=500");
-//     }
-
-//     #[test]
-//     fn test_container_iter() {
-//         let mut parser = JsonParser::new();
-//         parser.parse_main_json();
-//         let mut i = 0;
-//         while !matches!(parser.get_container(i), Value::Null) {
-//             i += 1;
-//         }
-//         // There are 13 upper level containers in sample dataset
-//         assert_eq!(i, 13);
-//     }
-
-//     #[test]
-//     fn test_container_item_iter() {
-//         let mut parser = JsonParser::new();
-//         parser.parse_main_json();
-//         let container = parser.get_container(0);
-//         let mut i = 0;
-//         while !matches!(JsonParser::get_container_item(container, i), Value::Null) {
-//             i += 1;
-//         }
-//         assert_eq!(i, 15);
-//     }
-
-//     #[test]
-//     fn test_parse_tiles() {
-//         let mut parser = JsonParser::new();
-//         parser.parse_main_json();
-//         let tile_rows = parser.parse_all_rows();
-//         println!("{:?}", tile_rows);
-//         println!("Rows: {}", tile_rows.len());
-//     }
-// }
+    }
+}
@@ -1,3 +1,18 @@
 pub mod async_resource_manager;
+pub mod content_source;
+pub mod differ;
+pub mod disk_cache;
+pub mod download_queue;
+pub mod exporter;
+pub mod failed_images;
+pub mod json_model;
 pub mod json_parser;
+pub mod library_source;
+pub mod media_dir_source;
+pub mod pending_downloads;
+pub mod recorder;
+pub mod row_loader;
+pub mod texture_cache;
 pub mod tiled_layout;
+pub mod validator;
+pub mod variants;
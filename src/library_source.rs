@@ -0,0 +1,309 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::content_source::{concurrency_limit, ContentSource};
+use crate::json_parser::{
+    decode, fetch_text, parent_dir, read_file, ContentType, ParseError, RowInfo, RowStyle, SetPage,
+    TileImage, TileInfo,
+};
+
+// A much simpler schema than bamgrid's, for media libraries that want to reuse the UI.
+// The library document lists the rows, and rows can point at separate set documents
+// that are loaded as they come close to the screen. Documents ending in .yaml or .yml
+// are read as yaml, anything else as json.
+//
+//     rows:
+//       - title: Featured
+//         style: hero
+//         tiles:
+//           - title: Big Buck Bunny
+//             image: https://media.example.com/bbb.jpg
+//       - title: Documentaries
+//         set: sets/docs-{offset}.json
+
+const DEFAULT_CONCURRENCY: usize = 4;
+// Used as img_url when a tile only lists images
+const LANDSCAPE_ASPECT_RATIO: f32 = 1.78;
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryDocument {
+    pub rows: Vec<LibraryRow>,
+}
+
+/// A row holds either its tiles, or the location of a set document holding them
+#[derive(Debug, Deserialize)]
+pub struct LibraryRow {
+    pub title: String,
+    /// Same styles as bamgrid containers, e.g. "hero" or "brand"
+    pub style: Option<String>,
+    #[serde(default)]
+    pub tiles: Vec<LibraryTile>,
+    /// Url or path relative to the library document.
+    /// If it contains `{offset}`, the set is paged and `{offset}` is replaced
+    /// by the index of each page's first tile.
+    pub set: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibrarySet {
+    pub tiles: Vec<LibraryTile>,
+    /// Number of tiles across every page, if the set is paged
+    pub total: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryTile {
+    pub title: String,
+    pub id: Option<String>,
+    /// "series", "program" or "collection", anything else is shown as is
+    #[serde(rename = "type")]
+    pub content_type: Option<String>,
+    /// The 1.78 image
+    pub image: Option<String>,
+    /// Images at other aspect ratios
+    #[serde(default)]
+    pub images: Vec<LibraryImage>,
+    pub rating: Option<String>,
+    pub year: Option<u32>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryImage {
+    pub aspect_ratio: f32,
+    pub url: String,
+    pub width: Option<u32>,
+}
+
+impl LibraryTile {
+    fn into_tile_info(self) -> TileInfo {
+        let mut images: Vec<TileImage> = self
+            .images
+            .into_iter()
+            .map(|image| TileImage {
+                aspect_ratio: image.aspect_ratio,
+                url: image.url,
+                master_width: image.width,
+            })
+            .collect();
+        images.sort_by(|a, b| a.aspect_ratio.total_cmp(&b.aspect_ratio));
+        let content_type = match self.content_type.as_deref() {
+            Some("series") => ContentType::Series,
+            Some("program") => ContentType::Program,
+            Some("collection") => ContentType::Collection,
+            other => ContentType::Unknown(other.map(str::to_string)),
+        };
+        let mut tile = TileInfo {
+            title: self.title,
+            img_url: String::new(),
+            images,
+            content_type,
+            content_id: self.id,
+            rating: self.rating,
+            release_year: self.year,
+            brief_description: self.description,
+            ..TileInfo::default()
+        };
+        tile.img_url = match self.image {
+            Some(image) => image,
+            None => tile.image_url_for(LANDSCAPE_ASPECT_RATIO).to_string(),
+        };
+        tile
+    }
+}
+
+/// Reads rows from a library document, see the top of this file for the schema
+pub struct LibrarySource {
+    /// Url or path of the library document
+    location: String,
+    client: Client,
+    concurrency: usize,
+}
+
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+fn is_yaml(location: &str) -> bool {
+    // Ignore any query string
+    let path = location.split('?').next().unwrap_or_default();
+    path.ends_with(".yaml") || path.ends_with(".yml")
+}
+
+impl LibrarySource {
+    pub fn new(location: String) -> Self {
+        Self {
+            location,
+            client: Client::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    pub fn set_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency_limit(concurrency);
+    }
+
+    // Sets are relative to the library document, like links in a web page
+    fn resolve(&self, location: &str) -> String {
+        if is_url(&self.location) {
+            Url::parse(&self.location)
+                .and_then(|base| base.join(location))
+                .map_or_else(|_| location.to_string(), |url| url.to_string())
+        } else if is_url(location) {
+            location.to_string()
+        } else {
+            parent_dir(Path::new(&self.location))
+                .join(location)
+                .display()
+                .to_string()
+        }
+    }
+
+    async fn read<T: DeserializeOwned>(&self, location: &str) -> Result<T, ParseError> {
+        let text = if is_url(location) {
+            fetch_text(&self.client, location).await?
+        } else {
+            read_file(Path::new(location)).await?
+        };
+        if is_yaml(location) {
+            serde_yaml::from_str(&text).map_err(|error| ParseError::Yaml {
+                location: location.to_string(),
+                error,
+            })
+        } else {
+            decode(location, &text)
+        }
+    }
+
+    // Unpaged sets are read whole for offset 0
+    async fn load_set_page(&self, set: &str, offset: usize) -> Result<SetPage, ParseError> {
+        let paged = set.contains("{offset}");
+        let location = self.resolve(&set.replace("{offset}", &offset.to_string()));
+        let doc: LibrarySet = self.read(&location).await?;
+        let tiles: Vec<TileInfo> = doc
+            .tiles
+            .into_iter()
+            .map(LibraryTile::into_tile_info)
+            .collect();
+        let loaded = offset + tiles.len();
+        Ok(SetPage {
+            tiles,
+            // The template doubles as the set's id, so later pages come back here
+            set_id: if paged { Some(set.to_string()) } else { None },
+            total: doc.total.unwrap_or(loaded).max(loaded),
+        })
+    }
+}
+
+#[async_trait]
+impl ContentSource for LibrarySource {
+    async fn load_rows(&mut self) -> Result<Vec<RowInfo>, ParseError> {
+        let doc: LibraryDocument = self.read(&self.location).await?;
+        let mut rows = Vec::new();
+        for (i, row) in doc.rows.into_iter().enumerate() {
+            let style = RowStyle::from_container_style(&row.style);
+            let row_info = match row.set {
+                Some(set) => RowInfo {
+                    title: row.title,
+                    style,
+                    tiles: Vec::new(),
+                    pending_ref: Some(set),
+                    set_id: None,
+                    total: 0,
+                },
+                None => {
+                    let tiles: Vec<TileInfo> = row
+                        .tiles
+                        .into_iter()
+                        .map(LibraryTile::into_tile_info)
+                        .collect();
+                    RowInfo {
+                        title: row.title,
+                        style,
+                        total: tiles.len(),
+                        tiles,
+                        pending_ref: None,
+                        set_id: None,
+                    }
+                }
+            };
+            if !row_info.tiles.is_empty() || row_info.pending_ref.is_some() {
+                rows.push(row_info);
+            } else {
+                eprintln!("Row {} has no tiles", i);
+            }
+        }
+        Ok(rows)
+    }
+
+    async fn load_ref_page(&self, ref_id: &str) -> Result<SetPage, ParseError> {
+        self.load_set_page(ref_id, 0).await
+    }
+
+    async fn load_page(&self, set_id: &str, offset: usize) -> Result<SetPage, ParseError> {
+        self.load_set_page(set_id, offset).await
+    }
+
+    fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixture_source() -> LibrarySource {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/library/library.yaml");
+        LibrarySource::new(path.display().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_load_rows() {
+        let mut source = fixture_source();
+        let rows = source.load_rows().await.unwrap();
+        // The row without tiles is dropped
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].style, RowStyle::Hero);
+        assert_eq!(rows[0].tiles[0].title, "Big Buck Bunny");
+        assert_eq!(rows[0].tiles[0].content_type, ContentType::Program);
+        assert_eq!(rows[0].tiles[0].release_year, Some(2008));
+        assert_eq!(
+            rows[1].pending_ref.as_deref(),
+            Some("sets/docs-{offset}.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_all_rows() {
+        let mut source = fixture_source();
+        let rows = source.load_all_rows().await.unwrap();
+        // Both pages of the paged set
+        let titles: Vec<_> = rows[1].tiles.iter().map(|t| &t.title).collect();
+        assert_eq!(titles, ["Cosmos", "Planet Earth", "Free Solo"]);
+        assert!(!rows[1].has_more_pages());
+
+        let series = &rows[2].tiles[0];
+        assert_eq!(series.content_type, ContentType::Series);
+        assert_eq!(
+            series.image_url_for(0.71),
+            "https://media.example.com/garden-poster.jpg"
+        );
+        // Picked from the images, since there's no image
+        assert_eq!(series.img_url, "https://media.example.com/garden.jpg");
+    }
+
+    #[tokio::test]
+    async fn test_yaml_error() {
+        let source = fixture_source();
+        let err = source.load_ref_page("sets/broken.yaml").await.unwrap_err();
+        assert!(matches!(err, ParseError::Yaml { .. }));
+    }
+}
@@ -1,22 +1,40 @@
 extern crate sdl2;
 
-use dss_now_playing::async_resource_manager::DownloadRequest;
+use dss_now_playing::async_resource_manager::{DownloadMessage, RetryPolicy};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use tokio::time::MissedTickBehavior;
 
+use reqwest::Client;
+use serde::Serialize;
+use std::fmt::Display;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time;
 
 // use dss_now_playing::json_parser::*;
 use dss_now_playing::async_resource_manager::download_loop;
 use dss_now_playing::async_resource_manager::AsyncResourceManager;
+use dss_now_playing::content_source::ContentSource;
+use dss_now_playing::differ::diff_catalogs;
+use dss_now_playing::disk_cache::DiskCache;
+use dss_now_playing::exporter::*;
 use dss_now_playing::json_parser::*;
+use dss_now_playing::library_source::LibrarySource;
+use dss_now_playing::media_dir_source::MediaDirectorySource;
+use dss_now_playing::recorder::*;
+use dss_now_playing::row_loader::*;
+use dss_now_playing::texture_cache::TextureBudget;
 use dss_now_playing::tiled_layout::*;
+use dss_now_playing::validator;
+use dss_now_playing::variants::VariantPrecedence;
 
-use clap::{AppSettings, Clap};
+use clap::{AppSettings, ArgSettings, Clap};
 
 const BACKGROUND_COLOR: Color = Color::BLACK;
 
@@ -29,21 +47,172 @@ const HEIGHT: u32 = 720;
 #[derive(Clap)]
 #[clap(version = "0.1", author = "Coy Humphrey <coy@coyhumphrey.com>")]
 #[clap(setting = AppSettings::ColoredHelp)]
+#[clap(setting = AppSettings::SubcommandsNegateReqs)]
 struct Opts {
     /// Slows image downloads to show off asynchronous behavior
     #[clap(short, long)]
     slow: bool,
-    /// Limits to a single active download
+    /// Limits to a single active download, same as --download-concurrency 1
     #[clap(short, long)]
     bounded: bool,
     /// Use multiple threads
     #[clap(short, long)]
     threaded: bool,
+    /// Json config file that can set base_url, set_path and page_path
+    #[clap(long, env = "DSS_CONFIG")]
+    config: Option<PathBuf>,
+    /// Base url the home and set json are downloaded from
+    #[clap(long, env = "DSS_BASE_URL")]
+    base_url: Option<String>,
+    /// Path of a set relative to the base url, `{}` is replaced by the set's refId
+    #[clap(long, env = "DSS_SET_PATH")]
+    set_path: Option<String>,
+    /// Path of a later page of a set relative to the base url, `{}` is replaced by the setId
+    /// and `{offset}` by the index of the page's first tile
+    #[clap(long, env = "DSS_PAGE_PATH")]
+    page_path: Option<String>,
+    /// Fetch referenced sets once their row is this many rows or less from the top of the screen
+    #[clap(long, default_value = "6")]
+    load_distance: usize,
+    /// Images of this many tiles on either side of each visible row are downloaded ahead of time
+    #[clap(long, default_value = "2")]
+    prefetch_tiles: usize,
+    /// Images of this many rows above and below the screen are downloaded ahead of time
+    #[clap(long, default_value = "1")]
+    prefetch_rows: usize,
+    /// Most images requested ahead of time each frame, closest to the screen first. 0 turns prefetching off
+    #[clap(long, default_value = "32")]
+    prefetch_budget: usize,
+    /// Milliseconds an image download is kept after its tile leaves the screen and the tiles prefetched
+    /// around it, even if it has started. 0 cancels it as soon as it does
+    #[clap(long, default_value = "500")]
+    cancel_after: u64,
+    /// Most set json downloaded at the same time
+    #[clap(long, default_value = "4")]
+    set_concurrency: usize,
+    /// Language tag to show text in, e.g. "fr" or "fr-CA". Falls back to the default text
+    #[clap(long, env = "DSS_LOCALE")]
+    locale: Option<String>,
+    /// Comma separated order of the variants a title or description is taken from
+    #[clap(long, require_delimiter = true)]
+    title_precedence: Option<Vec<String>>,
+    /// Comma separated order of the variants an image is taken from
+    #[clap(long, require_delimiter = true)]
+    image_precedence: Option<Vec<String>>,
+    /// Comma separated aspect ratios to draw rows of a style at, e.g. `poster=0.71,hero=3.91`.
+    /// Each row shows the image closest to its ratio
+    #[clap(long, require_delimiter = true)]
+    style_aspect_ratio: Option<Vec<StyleAspectRatio>>,
+    /// Print items whose variants disagree, or that have no variant in the precedence order
+    #[clap(long)]
+    report_variants: bool,
+    /// Read home.json and sets/<refId>.json from a local directory instead of downloading them
+    #[clap(long, env = "DSS_CONTENT_DIR", conflicts_with_all = &["base-url", "set-path", "page-path", "config"])]
+    content_dir: Option<PathBuf>,
+    /// Url or path of a library document in the generic yaml or json schema to read rows from,
+    /// instead of the bamgrid catalog
+    #[clap(long, env = "DSS_LIBRARY", conflicts_with_all = &["content-dir", "base-url", "set-path", "page-path", "config"])]
+    library: Option<String>,
+    /// Show a directory of images, one row per subdirectory and one tile per image,
+    /// instead of the bamgrid catalog
+    #[clap(long, env = "DSS_MEDIA_DIR", conflicts_with_all = &["library", "content-dir", "base-url", "set-path", "page-path", "config"])]
+    media_dir: Option<PathBuf>,
+    /// Most images downloaded at the same time, the selected tile and the rest of the screen go first
+    #[clap(long, default_value = "6")]
+    download_concurrency: usize,
+    /// Seconds before a download is abandoned
+    #[clap(long, default_value = "30")]
+    timeout: u64,
+    /// Times a failed image download is retried, unless the failure is permanent like a 404
+    #[clap(long, default_value = "3")]
+    retries: u32,
+    /// Milliseconds before the first retry of an image download, doubled for every retry after that
+    #[clap(long, default_value = "500")]
+    retry_delay: u64,
+    /// Seconds before an image whose retries all failed is requested again, unless the failure is permanent
+    #[clap(long, default_value = "30")]
+    retry_after: u64,
+    /// Directory downloaded images are cached in between runs. Defaults to the platform's cache directory
+    #[clap(long, env = "DSS_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+    /// Most megabytes of images kept in the cache, least recently used images are removed first
+    #[clap(long, default_value = "256")]
+    cache_size: u64,
+    /// Always download images instead of reading them from the cache
+    #[clap(long)]
+    no_cache: bool,
+    /// Most megabytes of image and text textures each kept in memory, textures that haven't been drawn
+    /// recently are dropped first. 0 for no limit
+    #[clap(long, default_value = "256")]
+    texture_memory: u64,
+    /// Most image and text textures each kept in memory
+    #[clap(long)]
+    max_textures: Option<usize>,
     /// TTF font file for displaying text
-    font_path: String,
+    #[clap(setting = ArgSettings::Required)]
+    font_path: Option<String>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// Mirror the catalog and its images to a directory that can be replayed with --content-dir
+    Record(RecordOpts),
+    /// Check every container of the catalog and report anything the app would skip or show empty
+    Validate(ValidateOpts),
+    /// Write every row and tile the app would show as json lines or csv
+    Export(ExportOpts),
+    /// Compare two catalogs and list the rows and tiles that changed
+    Diff(DiffOpts),
+}
+
+#[derive(Clap)]
+struct RecordOpts {
+    /// Directory to write the snapshot to
+    out_dir: PathBuf,
+}
+
+#[derive(Clap)]
+struct ValidateOpts {
+    /// Url or local path of the home json, or a snapshot directory.
+    /// Defaults to the catalog the app would show
+    target: Option<String>,
+    /// Print the report as json
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Clap)]
+struct DiffOpts {
+    /// Url or local path of the old home json, or a snapshot directory
+    old: String,
+    /// Url or local path of the new home json, or a snapshot directory.
+    /// Defaults to the catalog the app would show
+    new: Option<String>,
+    /// Print the changes as json
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Clap)]
+struct ExportOpts {
+    #[clap(long, default_value = "jsonl", possible_values = &["jsonl", "csv"])]
+    format: ExportFormat,
+    /// File to write to instead of stdout
+    #[clap(short, long)]
+    output: Option<PathBuf>,
 }
 
-async fn event_loop(row_infos: Vec<RowInfo>, tx: mpsc::Sender<DownloadRequest>, font_path: String) {
+async fn event_loop(
+    mut tile_set: TiledLayout,
+    mut row_loader: RowLoader,
+    tx: mpsc::Sender<DownloadMessage>,
+    font_path: String,
+    budget: TextureBudget,
+    retry: RetryPolicy,
+    cancel_after: Duration,
+) {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
@@ -65,12 +234,11 @@ async fn event_loop(row_infos: Vec<RowInfo>, tx: mpsc::Sender<DownloadRequest>,
     let font = ttf_context
         .load_font(font_path, HEIGHT as u16 / 36)
         .unwrap();
-    let mut texture_manager = AsyncResourceManager::new(&texture_creator, tx, font);
+    let mut texture_manager =
+        AsyncResourceManager::new(&texture_creator, tx, font, budget, retry, cancel_after);
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut tile_set = TiledLayout::new_with_row_infos(row_infos);
-
     // Arbitary target of 20 Frames per second
     let mut interval = time::interval(Duration::from_millis(1000 / 20));
     // When a tick is missed, treat it as Delayed. It will continue with the same interval
@@ -120,23 +288,166 @@ async fn event_loop(row_infos: Vec<RowInfo>, tx: mpsc::Sender<DownloadRequest>,
 
         // Handle completed download requests
         texture_manager.process_pending();
+        // Drop textures that are off screen if there are too many
+        texture_manager.end_frame();
+        // Fill in rows whose sets arrived, and request sets for rows coming into view
+        tile_set.update_rows(&mut row_loader);
 
         // Wait for next frame
         interval.tick().await;
     }
 }
 
-pub fn main() {
-    let opts: Opts = Opts::parse();
+// Command line and environment take precedence over the config file,
+// which takes precedence over the defaults
+fn endpoints_from_opts(opts: &Opts) -> Result<Endpoints, ParseError> {
+    let mut endpoints = match &opts.config {
+        Some(path) => Endpoints::from_config_file(path)?,
+        None => Endpoints::default(),
+    };
+    if let Some(base_url) = &opts.base_url {
+        endpoints.base_url = base_url.clone();
+    }
+    if let Some(set_path) = &opts.set_path {
+        endpoints.set_path = set_path.clone();
+    }
+    if let Some(page_path) = &opts.page_path {
+        endpoints.page_path = page_path.clone();
+    }
+    Ok(endpoints)
+}
+
+fn precedence_from_opts(opts: &Opts) -> VariantPrecedence {
+    let mut precedence = VariantPrecedence::default();
+    if let Some(text) = &opts.title_precedence {
+        precedence.text = text.clone();
+    }
+    if let Some(image) = &opts.image_precedence {
+        precedence.image = image.clone();
+    }
+    precedence
+}
+
+// Everything but the source and client, which depend on the command
+fn parser_from_opts(source: DocumentSource, opts: &Opts) -> JsonParser {
+    let mut json_parser = JsonParser::with_source(source);
+    json_parser.set_concurrency(opts.set_concurrency);
+    json_parser.set_variant_precedence(precedence_from_opts(opts));
+    json_parser.set_report_variants(opts.report_variants);
+    json_parser.set_locale(opts.locale.clone());
+    json_parser
+}
+
+fn source_from_opts(opts: &Opts) -> Result<DocumentSource, ParseError> {
+    match &opts.content_dir {
+        Some(dir) => Ok(DocumentSource::Directory(dir.clone())),
+        None => Ok(DocumentSource::Http(endpoints_from_opts(opts)?)),
+    }
+}
+
+// The bamgrid catalog, unless a library document or media directory was given
+fn content_source_from_opts(
+    opts: &Opts,
+    client: Client,
+) -> Result<Box<dyn ContentSource>, ParseError> {
+    if let Some(dir) = &opts.media_dir {
+        return Ok(Box::new(MediaDirectorySource::new(dir.clone())));
+    }
+    match &opts.library {
+        Some(location) => {
+            let mut library = LibrarySource::new(location.clone());
+            library.set_client(client);
+            library.set_concurrency(opts.set_concurrency);
+            Ok(Box::new(library))
+        }
+        None => {
+            let mut json_parser = parser_from_opts(source_from_opts(opts)?, opts);
+            json_parser.set_client(client);
+            Ok(Box::new(json_parser))
+        }
+    }
+}
+
+// Commands that work on the bamgrid json itself can't use a library document or media directory
+fn require_catalog(opts: &Opts, command: &str) {
+    if opts.library.is_some() || opts.media_dir.is_some() {
+        eprintln!("{} does not support --library or --media-dir", command);
+        std::process::exit(1);
+    }
+}
+
+fn retry_from_opts(opts: &Opts) -> RetryPolicy {
+    RetryPolicy {
+        max_retries: opts.retries,
+        base_delay: Duration::from_millis(opts.retry_delay),
+        retry_after: Duration::from_secs(opts.retry_after),
+        ..RetryPolicy::default()
+    }
+}
+
+fn download_concurrency_from_opts(opts: &Opts) -> usize {
+    if opts.bounded {
+        1
+    } else {
+        opts.download_concurrency
+    }
+}
+
+// Every request gives up after the timeout, json as well as images
+fn client_from_opts(opts: &Opts) -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(opts.timeout))
+        .build()
+        .unwrap()
+}
+
+fn texture_budget_from_opts(opts: &Opts) -> TextureBudget {
+    TextureBudget {
+        max_count: opts.max_textures,
+        max_bytes: match opts.texture_memory {
+            0 => None,
+            megabytes => Some(megabytes.saturating_mul(1024 * 1024)),
+        },
+    }
+}
 
-    // Parse all json upfront.
-    // We need at least the main file parsed before we can display anything useful.
-    // Ideally we would parse refs only as needed, but for this POC we simplify
-    // by parsing refs at the same time.
-    let mut json_parser = JsonParser::new();
-    let row_infos = json_parser.parse_all_rows();
+// The image cache is only an optimization, so the app runs without it if it can't be opened
+fn cache_from_opts(opts: &Opts) -> Option<Arc<DiskCache>> {
+    if opts.no_cache {
+        return None;
+    }
+    let dir = opts.cache_dir.clone().or_else(DiskCache::default_dir)?;
+    match DiskCache::open(dir.clone(), opts.cache_size.saturating_mul(1024 * 1024)) {
+        Ok(cache) => Some(Arc::new(cache)),
+        Err(e) => {
+            eprintln!(
+                "Not caching images, could not open {}: {}",
+                dir.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+// Prints a readable message and exits instead of panicking with a backtrace
+fn or_exit<T, E: Display>(result: Result<T, E>, context: &str) -> T {
+    match result {
+        Ok(val) => val,
+        Err(e) => {
+            eprintln!("{}: {}", context, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Reports and diffs only hold strings and numbers, so they always serialize
+fn print_json<T: Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
 
-    let rt = if opts.threaded {
+fn build_runtime(opts: &Opts) -> tokio::runtime::Runtime {
+    if opts.threaded {
         tokio::runtime::Builder::new_multi_thread()
     } else {
         tokio::runtime::Builder::new_current_thread()
@@ -144,11 +455,242 @@ pub fn main() {
     .enable_time()
     .enable_io()
     .build()
-    .unwrap();
-    // Channel to allow event loop to request image downloads
+    .unwrap()
+}
+
+fn record(opts: &Opts, record_opts: &RecordOpts) {
+    require_catalog(opts, "record");
+    let out_dir = &record_opts.out_dir;
+    let source = or_exit(source_from_opts(opts), "Could not load catalog");
+    let client = client_from_opts(opts);
+
+    let rt = build_runtime(opts);
     let (tx, rx) = mpsc::channel(16);
+    // A snapshot should hold what the server has now, not what was cached
+    rt.spawn(download_loop(
+        rx,
+        client.clone(),
+        None,
+        retry_from_opts(opts),
+        opts.slow,
+        download_concurrency_from_opts(opts),
+    ));
+    let result = rt.block_on(async {
+        record_documents(&source, &client, out_dir).await?;
+        // Parse the recorded copy, so the images match exactly what a replay will show
+        let mut json_parser = parser_from_opts(DocumentSource::Directory(out_dir.clone()), opts);
+        let row_infos = json_parser.parse_all_rows().await?;
+        let summary = record_images(&row_infos, out_dir, tx).await?;
+        Ok::<_, RecordError>((row_infos.len(), summary))
+    });
+    let (rows, summary) = or_exit(result, "Could not record catalog");
+
+    println!(
+        "Recorded {} rows and {} images to {}",
+        rows,
+        summary.images,
+        out_dir.display()
+    );
+    if !summary.failed.is_empty() {
+        eprintln!("Could not download {} images:", summary.failed.len());
+        for error in &summary.failed {
+            eprintln!("    {}", error);
+        }
+        std::process::exit(1);
+    }
+}
+
+// A url keeps the set and page paths from the options, and reads the sets from next to the home json
+fn target_source(opts: &Opts, target: &str) -> Result<DocumentSource, ParseError> {
+    if target.starts_with("http://") || target.starts_with("https://") {
+        let mut endpoints = endpoints_from_opts(opts)?;
+        let (base_url, home_path) = Endpoints::split_home_url(target)?;
+        endpoints.base_url = base_url;
+        endpoints.home_path = home_path;
+        Ok(DocumentSource::Http(endpoints))
+    } else if Path::new(target).is_dir() {
+        Ok(DocumentSource::Directory(PathBuf::from(target)))
+    } else {
+        Ok(DocumentSource::File(PathBuf::from(target)))
+    }
+}
+
+fn validate(opts: &Opts, validate_opts: &ValidateOpts) {
+    require_catalog(opts, "validate");
+    let source = match &validate_opts.target {
+        Some(target) => target_source(opts, target),
+        None => source_from_opts(opts),
+    };
+    let source = or_exit(source, "Could not load catalog");
+    let json_parser = parser_from_opts(source, opts);
+
+    let rt = build_runtime(opts);
+    let report = or_exit(
+        rt.block_on(validator::validate(&json_parser)),
+        "Could not load catalog",
+    );
+    if validate_opts.json {
+        print_json(&report);
+    } else {
+        println!("{}", report);
+    }
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+}
+
+fn export(opts: &Opts, export_opts: &ExportOpts) {
+    let mut source = or_exit(
+        content_source_from_opts(opts, client_from_opts(opts)),
+        "Could not load catalog",
+    );
+
+    let rt = build_runtime(opts);
+    let rows = or_exit(
+        rt.block_on(source.load_all_rows()),
+        "Could not load catalog",
+    );
+    let result = match &export_opts.output {
+        Some(path) => {
+            File::create(path).and_then(|file| write_export(&rows, export_opts.format, file))
+        }
+        None => write_export(&rows, export_opts.format, io::stdout().lock()),
+    };
+    or_exit(result, "Could not write export");
+}
+
+fn diff(opts: &Opts, diff_opts: &DiffOpts) {
+    require_catalog(opts, "diff");
+    let old_source = target_source(opts, &diff_opts.old);
+    let new_source = match &diff_opts.new {
+        Some(target) => target_source(opts, target),
+        None => source_from_opts(opts),
+    };
+    let rt = build_runtime(opts);
+    let load = |source: Result<DocumentSource, ParseError>| {
+        let source = or_exit(source, "Could not load catalog");
+        let mut json_parser = parser_from_opts(source, opts);
+        // Snapshots are compared by their original image urls
+        json_parser.set_use_local_images(false);
+        or_exit(
+            rt.block_on(json_parser.parse_all_rows()),
+            "Could not load catalog",
+        )
+    };
+    let old_rows = load(old_source);
+    let new_rows = load(new_source);
+
+    let diff = diff_catalogs(&old_rows, &new_rows);
+    if diff_opts.json {
+        print_json(&diff);
+    } else {
+        print!("{}", diff);
+    }
+    // Same as diff(1), so scripts can tell whether anything changed
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+pub fn main() {
+    let opts: Opts = Opts::parse();
+
+    match &opts.command {
+        Some(Command::Record(record_opts)) => return record(&opts, record_opts),
+        Some(Command::Validate(validate_opts)) => return validate(&opts, validate_opts),
+        Some(Command::Export(export_opts)) => return export(&opts, export_opts),
+        Some(Command::Diff(diff_opts)) => return diff(&opts, diff_opts),
+        None => (),
+    }
+
+    // Shared by the content source and the download loop, so both use the same connection pool
+    let client = client_from_opts(&opts);
+    let source = or_exit(
+        content_source_from_opts(&opts, client.clone()),
+        "Could not load catalog",
+    );
+
+    // The window opens right away and shows a loading message until the main json is parsed.
+    // Rows for referenced sets start out as placeholders, and are fetched
+    // as they come close to the screen.
+    let mut tile_set = TiledLayout::new_loading();
+    tile_set.set_load_distance(opts.load_distance);
+    for style_ratio in opts.style_aspect_ratio.iter().flatten() {
+        tile_set.set_style_aspect_ratio(style_ratio);
+    }
+    tile_set.set_prefetch(
+        opts.prefetch_tiles,
+        opts.prefetch_rows,
+        opts.prefetch_budget,
+    );
+
+    let rt = build_runtime(&opts);
+    // Channel to allow event loop to request image downloads
+    // Requests that don't fit are sent again next frame, so there's room for a screen and its prefetches
+    let (tx, rx) = mpsc::channel(64);
     // Infinite loop that processes download requests from main event loop
-    rt.spawn(download_loop(rx, opts.slow, opts.bounded));
+    rt.spawn(download_loop(
+        rx,
+        client,
+        cache_from_opts(&opts),
+        retry_from_opts(&opts),
+        opts.slow,
+        download_concurrency_from_opts(&opts),
+    ));
+    // Loads the rows, then handles requests for the sets of placeholder rows
+    let (row_tx, row_rx) = mpsc::channel(16);
+    let (main_tx, main_rx) = oneshot::channel();
+    rt.spawn(row_loop(row_rx, source, main_tx));
     // Infinite loop that updates display and handles user input
-    rt.block_on(event_loop(row_infos, tx, opts.font_path));
+    let budget = texture_budget_from_opts(&opts);
+    let retry = retry_from_opts(&opts);
+    // font_path is required when there's no subcommand
+    let font_path = opts.font_path.unwrap();
+    rt.block_on(event_loop(
+        tile_set,
+        RowLoader::new(row_tx, main_rx),
+        tx,
+        font_path,
+        budget,
+        retry,
+        Duration::from_millis(opts.cancel_after),
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_endpoints_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("endpoints.json");
+        std::fs::write(
+            &config,
+            r#"{"base_url": "https://config.example.com/", "set_path": "config/{}.json"}"#,
+        )
+        .unwrap();
+        let config = config.to_str().unwrap();
+
+        let opts =
+            Opts::try_parse_from(["dss-now-playing", "--config", config, "font.ttf"]).unwrap();
+        let endpoints = endpoints_from_opts(&opts).unwrap();
+        assert_eq!(endpoints.base_url, "https://config.example.com/");
+        assert_eq!(endpoints.set_path, "config/{}.json");
+
+        // The command line wins over the config file
+        let opts = Opts::try_parse_from([
+            "dss-now-playing",
+            "--config",
+            config,
+            "--base-url",
+            "https://cli.example.com/",
+            "font.ttf",
+        ])
+        .unwrap();
+        let endpoints = endpoints_from_opts(&opts).unwrap();
+        assert_eq!(endpoints.base_url, "https://cli.example.com/");
+        assert_eq!(endpoints.set_path, "config/{}.json");
+        assert_eq!(endpoints.page_path, Endpoints::default().page_path);
+    }
 }
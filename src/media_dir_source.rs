@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use reqwest::Url;
+
+use crate::content_source::ContentSource;
+use crate::json_parser::{ParseError, RowInfo, RowStyle, SetPage, TileInfo};
+
+// Builds rows straight from a directory of images, for kiosks showing local assets.
+// Every directory below the root that holds images becomes a row, titled by its path,
+// and every image in it a tile, titled by its file name. Tiles point at file:// urls,
+// which the download loop reads from disk.
+//
+//     media/
+//       Nature/
+//         mountain_lake.png     -> row "Nature", tile "mountain lake"
+//         Oceans/
+//           coral_reef.png      -> row "Nature / Oceans", tile "coral reef"
+//       Space/
+//         nebula.png            -> row "Space", tile "nebula"
+
+// Formats SDL_image can decode
+const IMAGE_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "gif", "bmp", "webp", "tga"];
+
+/// Reads rows from a directory tree of images, see the top of this file for the layout
+pub struct MediaDirectorySource {
+    root: PathBuf,
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> ParseError + '_ {
+    move |error| ParseError::Io {
+        path: path.to_path_buf(),
+        error,
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+// Follows symlinks to files. Symlinked directories aren't followed, so there can't be cycles.
+async fn is_file(path: &Path) -> bool {
+    matches!(tokio::fs::metadata(path).await, Ok(metadata) if metadata.is_file())
+}
+
+// Subdirectories and images of dir, each sorted by name. Hidden entries are skipped.
+async fn read_sorted(dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), ParseError> {
+    let mut subdirs = Vec::new();
+    let mut images = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await.map_err(io_error(dir))?;
+    while let Some(entry) = entries.next_entry().await.map_err(io_error(dir))? {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let file_type = entry.file_type().await.map_err(io_error(&path))?;
+        if file_type.is_dir() {
+            subdirs.push(path);
+        } else if is_image(&path) && is_file(&path).await {
+            images.push(path);
+        }
+    }
+    subdirs.sort();
+    images.sort();
+    Ok((subdirs, images))
+}
+
+fn tile_info(root: &Path, path: &Path) -> TileInfo {
+    let title = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().replace('_', " "))
+        .unwrap_or_default();
+    TileInfo {
+        title,
+        // The path is absolute, so this always succeeds
+        img_url: Url::from_file_path(path)
+            .map(|url| url.to_string())
+            .unwrap_or_default(),
+        content_id: path
+            .strip_prefix(root)
+            .ok()
+            .map(|relative| relative.display().to_string()),
+        ..TileInfo::default()
+    }
+}
+
+fn row_title(root: &Path, dir: &Path) -> String {
+    let relative = dir.strip_prefix(root).unwrap_or(dir);
+    let parts: Vec<_> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    parts.join(" / ")
+}
+
+impl MediaDirectorySource {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+#[async_trait]
+impl ContentSource for MediaDirectorySource {
+    async fn load_rows(&mut self) -> Result<Vec<RowInfo>, ParseError> {
+        // file:// urls have to be absolute
+        let root = self.root.canonicalize().map_err(io_error(&self.root))?;
+        let mut rows = Vec::new();
+        // Depth first, so nested rows come right after their parent
+        let mut stack = vec![root.clone()];
+        while let Some(dir) = stack.pop() {
+            let (subdirs, images) = read_sorted(&dir).await?;
+            // Reversed so they're popped in name order
+            stack.extend(subdirs.into_iter().rev());
+            // Images directly in the root don't belong to a row
+            if dir == root || images.is_empty() {
+                continue;
+            }
+            let tiles: Vec<TileInfo> = images.iter().map(|path| tile_info(&root, path)).collect();
+            rows.push(RowInfo {
+                title: row_title(&root, &dir),
+                style: RowStyle::Standard,
+                total: tiles.len(),
+                tiles,
+                pending_ref: None,
+                set_id: None,
+            });
+        }
+        Ok(rows)
+    }
+
+    // Every row is complete after load_rows, there are no placeholders or later pages
+    async fn load_ref_page(&self, _ref_id: &str) -> Result<SetPage, ParseError> {
+        Ok(SetPage::default())
+    }
+
+    async fn load_page(&self, _set_id: &str, _offset: usize) -> Result<SetPage, ParseError> {
+        Ok(SetPage::default())
+    }
+
+    fn concurrency(&self) -> usize {
+        1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_rows() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/media");
+        let mut source = MediaDirectorySource::new(root);
+        let rows = source.load_rows().await.unwrap();
+
+        let titles: Vec<_> = rows.iter().map(|row| &row.title).collect();
+        assert_eq!(titles, ["Nature", "Nature / Oceans", "Space"]);
+        // notes.txt is not an image
+        let tiles: Vec<_> = rows[2].tiles.iter().map(|tile| &tile.title).collect();
+        assert_eq!(tiles, ["earth at night", "nebula"]);
+        let tile = &rows[1].tiles[0];
+        assert!(tile.img_url.starts_with("file://"));
+        assert!(tile.img_url.ends_with("/Nature/Oceans/coral_reef.png"));
+        assert_eq!(
+            tile.content_id.as_deref().map(Path::new),
+            Some(Path::new("Nature/Oceans/coral_reef.png"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_root() {
+        let mut source = MediaDirectorySource::new(PathBuf::from("does-not-exist"));
+        let err = source.load_rows().await.unwrap_err();
+        assert!(matches!(err, ParseError::Io { .. }));
+    }
+}
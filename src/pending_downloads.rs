@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use futures::future::AbortHandle;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot::{self, error::TryRecvError},
+};
+
+use crate::async_resource_manager::{DownloadMessage, DownloadRequest, DownloadResult};
+use crate::download_queue::Priority;
+
+// Requests the download loop hasn't answered yet, and how urgently each image is wanted.
+// Images are asked for every frame they're drawn or prefetched. Once one isn't, its request
+// goes to the back of the queue, and after cancel_after it's cancelled: dropping the receiver
+// takes it out of the queue, and aborting stops it if it has already started.
+
+struct Pending {
+    rx: oneshot::Receiver<DownloadResult>,
+    // Stops the download, whether it's queued or already started
+    abort: AbortHandle,
+    // What the download loop was last told
+    priority: Priority,
+    // Most urgent priority the image was asked for with this frame, None if it wasn't
+    wanted: Option<Priority>,
+    last_wanted: Instant,
+}
+
+pub struct PendingDownloads {
+    pending: HashMap<String, Pending>,
+    tx: mpsc::Sender<DownloadMessage>,
+    cancel_after: Duration,
+}
+
+impl PendingDownloads {
+    pub fn new(tx: mpsc::Sender<DownloadMessage>, cancel_after: Duration) -> Self {
+        Self {
+            pending: HashMap::new(),
+            tx,
+            cancel_after,
+        }
+    }
+
+    /// Asks for an image this frame, requesting it unless it's already on its way
+    pub fn want(&mut self, url: &str, priority: Priority, now: Instant) {
+        if let Some(entry) = self.pending.get_mut(url) {
+            entry.wanted = Some(entry.wanted.map_or(priority, |wanted| wanted.min(priority)));
+            entry.last_wanted = now;
+            return;
+        }
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let (abort, registration) = AbortHandle::new_pair();
+        let msg = DownloadMessage::Fetch(DownloadRequest {
+            url: url.to_string(),
+            priority,
+            response_channel: resp_tx,
+            abort: Some(registration),
+        });
+        match self.tx.try_send(msg) {
+            Ok(_) => {
+                let entry = Pending {
+                    rx: resp_rx,
+                    abort,
+                    priority,
+                    wanted: Some(priority),
+                    last_wanted: now,
+                };
+                self.pending.insert(url.to_string(), entry);
+            }
+            // If other side is closed, we cannot recover
+            Err(TrySendError::Closed(_)) => panic!("Downloader closed unexpectedly"),
+            // Channel is full, it's requested again next frame
+            Err(TrySendError::Full(_)) => (),
+        }
+    }
+
+    /// Takes the downloads that were answered since the last call
+    pub fn finished(&mut self) -> Vec<(String, DownloadResult)> {
+        let mut finished = Vec::new();
+        self.pending.retain(|url, entry| match entry.rx.try_recv() {
+            Ok(result) => {
+                finished.push((url.clone(), result));
+                false
+            }
+            // If other side closed unexpectedly, it's requested again next time it's wanted
+            Err(TryRecvError::Closed) => false,
+            Err(TryRecvError::Empty) => true,
+        });
+        finished
+    }
+
+    /// Called once per frame. Tells the download loop about requests whose priority changed,
+    /// and cancels the ones that haven't been asked for within cancel_after.
+    pub fn update_priorities(&mut self, now: Instant) {
+        let tx = &self.tx;
+        let cancel_after = self.cancel_after;
+        self.pending.retain(|url, entry| {
+            let priority = match entry.wanted.take() {
+                Some(priority) => priority,
+                None if now.saturating_duration_since(entry.last_wanted) >= cancel_after => {
+                    entry.abort.abort();
+                    return false;
+                }
+                None => Priority::Offscreen,
+            };
+            if priority != entry.priority {
+                let msg = DownloadMessage::SetPriority {
+                    url: url.clone(),
+                    priority,
+                };
+                // If the channel is full, it's sent again next frame
+                if tx.try_send(msg).is_ok() {
+                    entry.priority = priority;
+                }
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{
+        future::{AbortRegistration, Abortable},
+        FutureExt,
+    };
+
+    const URL: &str = "https://example.com/a.jpg";
+
+    // What was sent to the download loop, without waiting for anything
+    fn sent(rx: &mut mpsc::Receiver<DownloadMessage>) -> Option<DownloadMessage> {
+        rx.recv().now_or_never().flatten()
+    }
+
+    fn fetched(rx: &mut mpsc::Receiver<DownloadMessage>) -> DownloadRequest {
+        match sent(rx) {
+            Some(DownloadMessage::Fetch(req)) => req,
+            _ => panic!("Expected a request"),
+        }
+    }
+
+    async fn is_aborted(registration: AbortRegistration) -> bool {
+        Abortable::new(futures::future::pending::<()>(), registration)
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn test_demote_then_cancel() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut downloads = PendingDownloads::new(tx, Duration::from_secs(1));
+        let start = Instant::now();
+        downloads.want(URL, Priority::Visible, start);
+        downloads.want(URL, Priority::Selected, start);
+        let req = fetched(&mut rx);
+        assert_eq!(req.priority, Priority::Visible);
+        // Only one request per url
+        assert!(sent(&mut rx).is_none());
+
+        // Selected this frame
+        downloads.update_priorities(start);
+        assert!(matches!(
+            sent(&mut rx),
+            Some(DownloadMessage::SetPriority {
+                priority: Priority::Selected,
+                ..
+            })
+        ));
+
+        // Scrolled away
+        downloads.update_priorities(start + Duration::from_millis(100));
+        assert!(matches!(
+            sent(&mut rx),
+            Some(DownloadMessage::SetPriority {
+                priority: Priority::Offscreen,
+                ..
+            })
+        ));
+        assert!(!req.response_channel.is_closed());
+
+        downloads.update_priorities(start + Duration::from_secs(1));
+        assert!(downloads.pending.is_empty());
+        assert!(req.response_channel.is_closed());
+        assert!(is_aborted(req.abort.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_right_away() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut downloads = PendingDownloads::new(tx, Duration::ZERO);
+        let start = Instant::now();
+        downloads.want(URL, Priority::Prefetch, start);
+        let req = fetched(&mut rx);
+        downloads.update_priorities(start);
+        assert_eq!(downloads.pending.len(), 1);
+
+        // Not even prefetched the next frame, so it's cancelled without going to the back first
+        downloads.update_priorities(start + Duration::from_millis(16));
+        assert!(downloads.pending.is_empty());
+        assert!(sent(&mut rx).is_none());
+        assert!(is_aborted(req.abort.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn test_finished() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut downloads = PendingDownloads::new(tx, Duration::from_secs(1));
+        let now = Instant::now();
+        downloads.want(URL, Priority::Visible, now);
+        downloads.want("https://example.com/b.jpg", Priority::Visible, now);
+        let a = fetched(&mut rx);
+        let b = fetched(&mut rx);
+        assert!(downloads.finished().is_empty());
+
+        let error = crate::async_resource_manager::DownloadError::Timeout { url: a.url };
+        assert!(a.response_channel.send(Err(error)).is_ok());
+        let finished = downloads.finished();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].0, URL);
+        // Dropped without an answer, so it can be requested again
+        drop(b);
+        assert!(downloads.finished().is_empty());
+        assert!(downloads.pending.is_empty());
+    }
+}
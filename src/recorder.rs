@@ -0,0 +1,341 @@
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use reqwest::Client;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::async_resource_manager::{DownloadError, DownloadMessage, DownloadRequest};
+use crate::download_queue::Priority;
+use crate::json_model::{ContainerSet, CuratedSet, HomeDocument, SetDocument};
+use crate::json_parser::{
+    decode, DocumentSource, ParseError, RowInfo, SnapshotManifest, MANIFEST_PATH,
+};
+
+// Mirrors a catalog to disk so it can be replayed offline with DocumentSource::Directory.
+// The json documents are copied as-is, and every tile image is downloaded into images/
+// with a manifest mapping the original urls to the local files.
+
+#[derive(Debug)]
+pub enum RecordError {
+    Parse(ParseError),
+    Write {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// The download loop stopped before every image was answered
+    DownloaderClosed,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Parse(e) => e.fmt(f),
+            RecordError::Write { path, error } => {
+                write!(f, "could not write {}: {}", path.display(), error)
+            }
+            RecordError::DownloaderClosed => write!(f, "the image downloader stopped unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<ParseError> for RecordError {
+    fn from(e: ParseError) -> Self {
+        RecordError::Parse(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct RecordSummary {
+    pub images: usize,
+    /// Images that could not be downloaded. Replays will still try to download these.
+    pub failed: Vec<DownloadError>,
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), RecordError> {
+    let write_error = |error| RecordError::Write {
+        path: path.to_path_buf(),
+        error,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    fs::write(path, contents).map_err(write_error)
+}
+
+/// Copies the home json, every referenced set json and every further page of a set
+/// from source into out_dir.
+pub async fn record_documents(
+    source: &DocumentSource,
+    client: &Client,
+    out_dir: &Path,
+) -> Result<(), RecordError> {
+    // Reuse the Directory layout so the snapshot can be read back as-is
+    let snapshot = DocumentSource::Directory(out_dir.to_path_buf());
+
+    // A manifest from an older recording would point at images that may no longer match
+    let manifest_path = out_dir.join(MANIFEST_PATH);
+    if manifest_path.exists() {
+        fs::remove_file(&manifest_path).map_err(|error| RecordError::Write {
+            path: manifest_path.clone(),
+            error,
+        })?;
+    }
+
+    let home_location = source.home_location();
+    let home_text = source.read(client, &home_location).await?;
+    let home: HomeDocument = decode(&home_location, &home_text)?;
+    write_file(Path::new(&snapshot.home_location()), home_text.as_bytes())?;
+
+    for container in &home.data.standard_collection.containers {
+        match &container.set {
+            ContainerSet::CuratedSet(set)
+            | ContainerSet::TrendingSet(set)
+            | ContainerSet::PersonalizedCuratedSet(set) => {
+                if let Some(set_id) = &set.set_id {
+                    record_pages(source, client, &snapshot, set_id, set).await?;
+                }
+            }
+            ContainerSet::SetRef(set_ref) => {
+                let location = source.set_location(&set_ref.ref_id);
+                let text = source.read(client, &location).await?;
+                write_file(
+                    Path::new(&snapshot.set_location(&set_ref.ref_id)),
+                    text.as_bytes(),
+                )?;
+                let ref_set = decode::<SetDocument>(&location, &text)?.data;
+                let set_id = ref_set.page_id(&set_ref.ref_id).to_string();
+                record_pages(source, client, &snapshot, &set_id, &ref_set.into_set()).await?;
+            }
+            ContainerSet::Unknown => (),
+        }
+    }
+    Ok(())
+}
+
+// Copies the pages of a set that follow first_page, one after the other
+async fn record_pages(
+    source: &DocumentSource,
+    client: &Client,
+    snapshot: &DocumentSource,
+    set_id: &str,
+    first_page: &CuratedSet,
+) -> Result<(), RecordError> {
+    let hits = match &first_page.meta {
+        Some(meta) => meta.hits,
+        None => return Ok(()),
+    };
+    let mut loaded = first_page.items.len();
+    while loaded < hits {
+        let location = source.page_location(set_id, loaded);
+        let text = source.read(client, &location).await?;
+        write_file(
+            Path::new(&snapshot.page_location(set_id, loaded)),
+            text.as_bytes(),
+        )?;
+        let page = decode::<SetDocument>(&location, &text)?.data.into_set();
+        if page.items.is_empty() {
+            break;
+        }
+        loaded += page.items.len();
+    }
+    Ok(())
+}
+
+/// Downloads every tile image through the download loop on the other end of tx,
+/// then writes the manifest. Failed downloads are reported in the summary rather than
+/// aborting the recording.
+pub async fn record_images(
+    rows: &[RowInfo],
+    out_dir: &Path,
+    tx: mpsc::Sender<DownloadMessage>,
+) -> Result<RecordSummary, RecordError> {
+    let mut seen = HashSet::new();
+    // Every aspect ratio, since the layout may pick any of them
+    let urls: Vec<&str> = rows
+        .iter()
+        .flat_map(|row| row.tiles.iter())
+        .flat_map(|tile| {
+            std::iter::once(tile.img_url.as_str())
+                .chain(tile.images.iter().map(|image| image.url.as_str()))
+        })
+        .filter(|url| !url.is_empty() && seen.insert(*url))
+        .collect();
+
+    // Issue every request up front so the download loop can work on them concurrently
+    let mut pending = Vec::new();
+    for url in urls {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        // Nothing is on screen, so every image is as urgent as the next
+        let msg = DownloadMessage::Fetch(DownloadRequest {
+            url: url.to_string(),
+            priority: Priority::Visible,
+            response_channel: resp_tx,
+            // Every image is needed
+            abort: None,
+        });
+        if tx.send(msg).await.is_err() {
+            return Err(RecordError::DownloaderClosed);
+        }
+        pending.push((url, resp_rx));
+    }
+
+    let mut manifest = SnapshotManifest::default();
+    let mut failed = Vec::new();
+    for (i, (url, resp_rx)) in pending.into_iter().enumerate() {
+        match resp_rx.await {
+            Ok(Ok(resp)) => {
+                let file = format!("images/{}.{}", i, image_extension(&resp.bytes));
+                write_file(&out_dir.join(&file), &resp.bytes)?;
+                manifest.images.insert(url.to_string(), file);
+            }
+            Ok(Err(e)) => failed.push(e),
+            Err(_) => return Err(RecordError::DownloaderClosed),
+        }
+    }
+
+    let images = manifest.images.len();
+    // Serializing a map of strings can't fail
+    let manifest = serde_json::to_string_pretty(&manifest).unwrap();
+    write_file(&out_dir.join(MANIFEST_PATH), manifest.as_bytes())?;
+
+    Ok(RecordSummary { images, failed })
+}
+
+// SDL detects the format from the contents, the extension is just for humans browsing the snapshot
+fn image_extension(bytes: &[u8]) -> &'static str {
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        "jpg"
+    } else if bytes.starts_with(b"\x89PNG") {
+        "png"
+    } else {
+        "img"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::async_resource_manager::{download_loop, RetryPolicy};
+    use crate::json_parser::JsonParser;
+    use reqwest::Url;
+
+    // Copies the fixture snapshot, pointing every image at a local file so nothing is downloaded
+    fn local_snapshot(from: &Path, to: &Path, images: &[String], next: &mut usize) {
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                local_snapshot(&path, &target, images, next);
+                continue;
+            }
+            let mut json: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            point_urls_at(&mut json, images, next);
+            write_file(&target, json.to_string().as_bytes()).unwrap();
+        }
+    }
+
+    fn point_urls_at(json: &mut serde_json::Value, images: &[String], next: &mut usize) {
+        match json {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if key == "url" && value.is_string() {
+                        *value = images[*next % images.len()].clone().into();
+                        *next += 1;
+                    } else {
+                        point_urls_at(value, images, next);
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    point_urls_at(value, images, next);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn titles(rows: &[RowInfo]) -> Vec<(String, Vec<String>)> {
+        rows.iter()
+            .map(|row| {
+                let tiles = row.tiles.iter().map(|tile| tile.title.clone()).collect();
+                (row.title.clone(), tiles)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let images: Vec<String> = ["Nature/forest.png", "Space/nebula.png"]
+            .iter()
+            .map(|file| {
+                let path = fixtures.join("media").join(file).canonicalize().unwrap();
+                Url::from_file_path(path).unwrap().to_string()
+            })
+            .collect();
+        let source_dir = tempfile::tempdir().unwrap();
+        local_snapshot(
+            &fixtures.join("snapshot"),
+            source_dir.path(),
+            &images,
+            &mut 0,
+        );
+        let out_dir = tempfile::tempdir().unwrap();
+        let out = out_dir.path();
+        write_file(&out.join(MANIFEST_PATH), b"{\"images\": {}}").unwrap();
+
+        let source = DocumentSource::Directory(source_dir.path().to_path_buf());
+        let client = Client::new();
+        record_documents(&source, &client, out).await.unwrap();
+        // The old manifest is gone as soon as the documents are replaced
+        assert!(!out.join(MANIFEST_PATH).exists());
+
+        let mut parser = JsonParser::with_source(DocumentSource::Directory(out.to_path_buf()));
+        let rows = parser.parse_all_rows().await.unwrap();
+        let (tx, rx) = mpsc::channel(16);
+        let downloader = tokio::spawn(download_loop(
+            rx,
+            client,
+            None,
+            RetryPolicy::default(),
+            false,
+            2,
+        ));
+        let summary = record_images(&rows, out, tx).await.unwrap();
+        downloader.await.unwrap();
+        assert!(summary.failed.is_empty());
+        assert_eq!(summary.images, images.len());
+
+        // Urls are rewritten to files relative to the snapshot
+        let manifest: SnapshotManifest =
+            serde_json::from_str(&fs::read_to_string(out.join(MANIFEST_PATH)).unwrap()).unwrap();
+        assert_eq!(
+            manifest.images.keys().collect::<Vec<_>>(),
+            images.iter().collect::<Vec<_>>()
+        );
+        for file in manifest.images.values() {
+            assert!(file.starts_with("images/") && file.ends_with(".png"));
+            assert!(out.join(file).is_file());
+        }
+
+        // A replay shows the same rows, with the recorded copies of the images
+        let mut original = JsonParser::with_source(source);
+        let mut replay = JsonParser::with_source(DocumentSource::Directory(out.to_path_buf()));
+        let original_rows = original.parse_all_rows().await.unwrap();
+        let replay_rows = replay.parse_all_rows().await.unwrap();
+        assert_eq!(replay_rows.len(), 2);
+        assert_eq!(titles(&replay_rows), titles(&original_rows));
+        let out = out.canonicalize().unwrap();
+        for tile in replay_rows.iter().flat_map(|row| &row.tiles) {
+            let path = Url::parse(&tile.img_url).unwrap().to_file_path().unwrap();
+            assert!(path.starts_with(out.join("images")));
+        }
+    }
+}
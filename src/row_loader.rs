@@ -0,0 +1,276 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot::{self, error::TryRecvError},
+    Semaphore,
+};
+
+use crate::content_source::ContentSource;
+use crate::json_parser::{ParseError, RowInfo, SetPage};
+
+pub type MainResponse = Result<Vec<RowInfo>, ParseError>;
+pub type RowResponse = Result<SetPage, ParseError>;
+
+/// Which tiles a RowRequest is for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SetQuery {
+    /// First page of the set behind a placeholder row
+    Ref(String),
+    /// Page of a set starting at item offset
+    Page { set_id: String, offset: usize },
+}
+
+// Asks the row loop to fetch a page of a set,
+// and provides a channel to send back the tiles.
+pub struct RowRequest {
+    pub query: SetQuery,
+    pub response_channel: oneshot::Sender<RowResponse>,
+}
+
+// Meant to be spawned off as a "parallel" async task, like download_loop.
+// First loads the rows and sends them on main_channel, then loops handling
+// requests for the sets of placeholder rows and for further pages.
+// Up to source.concurrency() pages are fetched at once.
+pub async fn row_loop(
+    mut rx: mpsc::Receiver<RowRequest>,
+    mut source: Box<dyn ContentSource>,
+    main_channel: oneshot::Sender<MainResponse>,
+) {
+    let main = source.load_rows().await;
+    let failed = main.is_err();
+    if main_channel.send(main).is_err() {
+        println!("Response channel closed unexpectedly");
+    }
+    // Nothing will ever ask for a set
+    if failed {
+        return;
+    }
+
+    let source: Arc<dyn ContentSource> = Arc::from(source);
+    let permits = Arc::new(Semaphore::new(source.concurrency()));
+    while let Some(req) = rx.recv().await {
+        // Waiting here rather than in the task leaves extra requests queued in the channel
+        let permit = permits.clone().acquire_owned().await.unwrap();
+        let source = source.clone();
+        tokio::spawn(async move {
+            let resp = match &req.query {
+                SetQuery::Ref(ref_id) => source.load_ref_page(ref_id).await,
+                SetQuery::Page { set_id, offset } => source.load_page(set_id, *offset).await,
+            };
+            drop(permit);
+            if req.response_channel.send(resp).is_err() {
+                println!("Response channel closed unexpectedly");
+            }
+        });
+    }
+}
+
+// Event loop side of the row loop. Mirrors how AsyncResourceManager handles downloads:
+// requests are sent without blocking, and finished requests are polled once per frame.
+pub struct RowLoader {
+    main: Option<oneshot::Receiver<MainResponse>>,
+    in_progress: HashMap<SetQuery, oneshot::Receiver<RowResponse>>,
+    tx: mpsc::Sender<RowRequest>,
+}
+
+impl RowLoader {
+    pub fn new(tx: mpsc::Sender<RowRequest>, main: oneshot::Receiver<MainResponse>) -> Self {
+        Self {
+            main: Some(main),
+            in_progress: HashMap::new(),
+            tx,
+        }
+    }
+
+    // Returns the rows of the main json once, as soon as they are ready
+    pub fn process_main(&mut self) -> Option<MainResponse> {
+        let rx = self.main.as_mut()?;
+        match rx.try_recv() {
+            Ok(resp) => {
+                self.main = None;
+                Some(resp)
+            }
+            Err(TryRecvError::Closed) => panic!("Row loader closed unexpectedly"),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+
+    // Requests the set unless a request for it is already in flight
+    pub fn request(&mut self, ref_id: &str) {
+        self.send(SetQuery::Ref(ref_id.to_string()));
+    }
+
+    // Requests the page of the set starting at offset, unless it's already in flight
+    pub fn request_page(&mut self, set_id: &str, offset: usize) {
+        self.send(SetQuery::Page {
+            set_id: set_id.to_string(),
+            offset,
+        });
+    }
+
+    fn send(&mut self, query: SetQuery) {
+        if self.in_progress.contains_key(&query) {
+            return;
+        }
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let msg = RowRequest {
+            query: query.clone(),
+            response_channel: resp_tx,
+        };
+        match self.tx.try_send(msg) {
+            Ok(_) => {
+                self.in_progress.insert(query, resp_rx);
+            }
+            // If other side is closed, we cannot recover
+            Err(TrySendError::Closed(_)) => panic!("Row loader closed unexpectedly"),
+            // Channel is full, we'll be asked again next frame
+            Err(TrySendError::Full(_)) => (),
+        }
+    }
+
+    // Returns every request that finished since the last call
+    pub fn process_pending(&mut self) -> Vec<(SetQuery, RowResponse)> {
+        let mut finished = Vec::new();
+        let mut remove_set = Vec::new();
+        for (key, rx) in self.in_progress.iter_mut() {
+            match rx.try_recv() {
+                Ok(resp) => {
+                    remove_set.push(key.clone());
+                    finished.push((key.clone(), resp));
+                }
+                // If other side closed unexpectedly, we can remove it and try again later
+                Err(TryRecvError::Closed) => remove_set.push(key.clone()),
+                // Result not ready yet, we'll try again next tick
+                Err(TryRecvError::Empty) => (),
+            }
+        }
+
+        for key in remove_set {
+            self.in_progress.remove(&key);
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::json_parser::{DocumentSource, JsonParser, RowStyle};
+    use async_trait::async_trait;
+    use std::{
+        path::Path,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    fn spawn_row_loop(source: Box<dyn ContentSource>) -> RowLoader {
+        let (tx, rx) = mpsc::channel(16);
+        let (main_tx, main_rx) = oneshot::channel();
+        tokio::spawn(row_loop(rx, source, main_tx));
+        RowLoader::new(tx, main_rx)
+    }
+
+    // Polls like the event loop does once per frame
+    async fn wait_for_main(loader: &mut RowLoader) -> Vec<RowInfo> {
+        loop {
+            if let Some(main) = loader.process_main() {
+                return main.unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    async fn wait_for_pages(loader: &mut RowLoader, count: usize) -> Vec<(SetQuery, RowResponse)> {
+        let mut finished = Vec::new();
+        while finished.len() < count {
+            finished.extend(loader.process_pending());
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        finished
+    }
+
+    #[tokio::test]
+    async fn test_lazy_ref_sets() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/snapshot");
+        let parser = JsonParser::with_source(DocumentSource::Directory(dir));
+        let mut loader = spawn_row_loop(Box::new(parser));
+        let rows = wait_for_main(&mut loader).await;
+        // The referenced set isn't fetched until it's asked for
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].tiles.is_empty());
+        let ref_id = rows[1].pending_ref.clone().unwrap();
+        assert!(loader.process_main().is_none());
+
+        // Asking again while the first request is in flight doesn't send another
+        loader.request(&ref_id);
+        loader.request(&ref_id);
+        assert_eq!(loader.in_progress.len(), 1);
+        let mut finished = wait_for_pages(&mut loader, 1).await;
+        let (query, page) = finished.pop().unwrap();
+        assert_eq!(query, SetQuery::Ref(ref_id.clone()));
+        let page = page.unwrap();
+        assert_eq!(page.tiles.len(), 2);
+        assert_eq!(page.total, 3);
+        assert!(loader.in_progress.is_empty());
+
+        let set_id = page.set_id.unwrap();
+        loader.request_page(&set_id, 2);
+        let (query, page) = wait_for_pages(&mut loader, 1).await.pop().unwrap();
+        assert_eq!(query, SetQuery::Page { set_id, offset: 2 });
+        assert_eq!(page.unwrap().tiles[0].title, "Andor");
+    }
+
+    // Counts how many pages are being loaded at the same time
+    #[derive(Default)]
+    struct Loading {
+        now: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    // One placeholder row, and ref pages that take a while to load
+    struct SlowSource(Arc<Loading>);
+
+    #[async_trait]
+    impl ContentSource for SlowSource {
+        async fn load_rows(&mut self) -> Result<Vec<RowInfo>, ParseError> {
+            Ok(vec![RowInfo {
+                title: "Row".to_string(),
+                style: RowStyle::Standard,
+                tiles: Vec::new(),
+                pending_ref: Some("0".to_string()),
+                set_id: None,
+                total: 0,
+            }])
+        }
+
+        async fn load_ref_page(&self, _ref_id: &str) -> Result<SetPage, ParseError> {
+            let now = self.0.now.fetch_add(1, Ordering::SeqCst) + 1;
+            self.0.most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.0.now.fetch_sub(1, Ordering::SeqCst);
+            Ok(SetPage::default())
+        }
+
+        async fn load_page(&self, _set_id: &str, _offset: usize) -> Result<SetPage, ParseError> {
+            Ok(SetPage::default())
+        }
+
+        fn concurrency(&self) -> usize {
+            2
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_bound() {
+        let loading = Arc::new(Loading::default());
+        let mut loader = spawn_row_loop(Box::new(SlowSource(loading.clone())));
+        wait_for_main(&mut loader).await;
+        for ref_id in 0..6 {
+            loader.request(&ref_id.to_string());
+        }
+        let finished = wait_for_pages(&mut loader, 6).await;
+        assert!(finished.iter().all(|(_, page)| page.is_ok()));
+        assert_eq!(loading.most.load(Ordering::SeqCst), 2);
+    }
+}
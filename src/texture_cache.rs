@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+// Textures live in GPU memory, so the resource manager can't keep every image it has ever
+// drawn. Entries remember the last frame they were used in, and once the cache is over
+// budget the least recently used ones are dropped at the end of a frame. Anything used in
+// the current frame is on screen and is never dropped, even if that leaves the cache
+// over budget. A dropped image is requested again the next time it's drawn.
+
+/// Limits on what a TextureCache holds, None means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextureBudget {
+    pub max_count: Option<usize>,
+    pub max_bytes: Option<u64>,
+}
+
+struct Entry<V> {
+    value: V,
+    bytes: u64,
+    last_used: u64,
+}
+
+pub struct TextureCache<V> {
+    entries: HashMap<String, Entry<V>>,
+    budget: TextureBudget,
+    total_bytes: u64,
+    frame: u64,
+}
+
+impl<V: Clone> TextureCache<V> {
+    pub fn new(budget: TextureBudget) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            total_bytes: 0,
+            frame: 0,
+        }
+    }
+
+    /// Returns the value and marks it as used in this frame
+    pub fn get(&mut self, key: &str) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.frame;
+        Some(entry.value.clone())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Adds a value taking up bytes of texture memory, marked as used in this frame
+    pub fn insert(&mut self, key: String, value: V, bytes: u64) {
+        let entry = Entry {
+            value,
+            bytes,
+            last_used: self.frame,
+        };
+        self.total_bytes += bytes;
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.total_bytes -= old.bytes;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    fn over_budget(&self) -> bool {
+        matches!(self.budget.max_count, Some(max) if self.entries.len() > max)
+            || matches!(self.budget.max_bytes, Some(max) if self.total_bytes > max)
+    }
+
+    /// Drops least recently used values not used in this frame until the cache is within budget,
+    /// then starts the next frame. Returns how many were dropped.
+    pub fn end_frame(&mut self) -> usize {
+        let mut evicted = 0;
+        if self.over_budget() {
+            let mut unused: Vec<(u64, String)> = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.last_used < self.frame)
+                .map(|(key, entry)| (entry.last_used, key.clone()))
+                .collect();
+            unused.sort();
+            for (_, key) in unused {
+                if !self.over_budget() {
+                    break;
+                }
+                if let Some(entry) = self.entries.remove(&key) {
+                    self.total_bytes -= entry.bytes;
+                    evicted += 1;
+                }
+            }
+        }
+        self.frame += 1;
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_drawn() {
+        let mut cache = TextureCache::new(TextureBudget {
+            max_count: Some(2),
+            max_bytes: None,
+        });
+        cache.insert("a".to_string(), 'a', 10);
+        cache.insert("b".to_string(), 'b', 10);
+        cache.end_frame();
+        cache.insert("c".to_string(), 'c', 10);
+        cache.get("a");
+        assert_eq!(cache.end_frame(), 1);
+        assert!(!cache.contains_key("b"));
+        assert_eq!(cache.get("a"), Some('a'));
+        assert_eq!(cache.total_bytes(), 20);
+    }
+
+    #[test]
+    fn test_keeps_textures_on_screen() {
+        let mut cache = TextureCache::new(TextureBudget {
+            max_count: None,
+            max_bytes: Some(15),
+        });
+        cache.insert("a".to_string(), 'a', 10);
+        cache.insert("b".to_string(), 'b', 10);
+        // Both are drawn this frame, so the cache stays over budget
+        assert_eq!(cache.end_frame(), 0);
+        assert_eq!(cache.len(), 2);
+        cache.get("b");
+        assert_eq!(cache.end_frame(), 1);
+        assert!(cache.contains_key("b"));
+        assert_eq!(cache.total_bytes(), 10);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let mut cache = TextureCache::new(TextureBudget::default());
+        for i in 0..100 {
+            cache.insert(i.to_string(), i, 1000);
+            cache.end_frame();
+        }
+        assert_eq!(cache.len(), 100);
+    }
+}
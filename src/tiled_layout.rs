@@ -1,15 +1,72 @@
 extern crate sdl2;
 
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::str::FromStr;
+
 use sdl2::pixels::Color;
 use sdl2::rect::Point;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
-use crate::async_resource_manager::AsyncResourceManager;
-use crate::json_parser::{RowInfo, TileInfo};
+use crate::async_resource_manager::{AsyncResourceManager, ImageState};
+use crate::download_queue::Priority;
+use crate::json_parser::{RowInfo, RowStyle, SetPage, TileInfo};
+use crate::row_loader::{RowLoader, RowResponse, SetQuery};
 
 const TILE_COLOR: Color = Color::BLUE;
+const FRAME_COLOR: Color = Color::GREY;
+// Tiles whose image failed to download or decode
+const BROKEN_COLOR: Color = Color::RGB(90, 20, 30);
+const BROKEN_BORDER_COLOR: Color = Color::RGB(170, 60, 70);
+// Space kept between the title of a broken tile and its edges
+const BROKEN_TITLE_MARGIN: u32 = 8;
+// The visible rows plus a couple on either side
+const DEFAULT_LOAD_DISTANCE: usize = 6;
+// Fetch a row's next page once the window is this many tiles or less from its last loaded tile
+const PAGE_LOAD_DISTANCE: usize = 4;
+// Images are requested ahead of time for this many tiles on either side of each visible row,
+// and for the tiles of this many rows above and below the screen
+const DEFAULT_PREFETCH_TILES: usize = 2;
+const DEFAULT_PREFETCH_ROWS: usize = 1;
+// Most images requested ahead of time per frame, closest to the screen first
+const DEFAULT_PREFETCH_BUDGET: usize = 32;
+// Landscape, used for any row style without its own aspect ratio
+const DEFAULT_ASPECT_RATIO: f32 = 1.78;
+const POSTER_ASPECT_RATIO: f32 = 0.71;
+const HERO_ASPECT_RATIO: f32 = 3.91;
+const CHARACTER_ASPECT_RATIO: f32 = 1.0;
+
+// Largest size with the given aspect ratio that fits within size
+fn fit_aspect_ratio(size: (u32, u32), aspect_ratio: f32) -> (u32, u32) {
+    let (width, height) = size;
+    let fitted_width = (height as f32 * aspect_ratio) as u32;
+    if fitted_width <= width {
+        (fitted_width, height)
+    } else {
+        (width, (width as f32 / aspect_ratio) as u32)
+    }
+}
+
+// Hero rows are this many rows tall
+const HERO_SLOTS: usize = 2;
+// Carousel position markers under hero rows
+const DOT_SIZE: u32 = 8;
+// Most dots under a hero banner, longer carousels only show the dots around the current tile
+const MAX_DOTS: usize = 15;
+
+// How a tile stands out from the rest of its row
+#[derive(Clone, Copy, PartialEq)]
+enum Highlight {
+    None,
+    // Thin frame, used for brand tiles
+    Framed,
+    // Grows and gets a white frame
+    Selected,
+    // White frame without growing, for tiles that already span the screen
+    SelectedInPlace,
+}
 
 struct Tile {
     tile_info: TileInfo,
@@ -25,32 +82,93 @@ impl Tile {
         canvas: &mut Canvas<Window>,
         texture_manager: &mut AsyncResourceManager,
         pos: Point,
-        width: u32,
-        height: u32,
-        selected: bool,
+        size: (u32, u32),
+        aspect_ratio: f32,
+        highlight: Highlight,
     ) {
-        let (width, height) = if selected {
+        let (width, height) = size;
+        let (width, height) = if highlight == Highlight::Selected {
             (width + width / 10, height + height / 10)
         } else {
             (width, height)
         };
 
-        if selected {
+        let frame_color = match highlight {
+            Highlight::None => None,
+            Highlight::Framed => Some(FRAME_COLOR),
+            Highlight::Selected | Highlight::SelectedInPlace => Some(Color::WHITE),
+        };
+        if let Some(frame_color) = frame_color {
             // +2 on width and height allows for a 1px wide outer layer
             let outer_rect = Rect::from_center(pos, width + 2, height + 2);
-            canvas.set_draw_color(Color::WHITE);
+            canvas.set_draw_color(frame_color);
             canvas.fill_rect(outer_rect).unwrap();
         }
 
         let rect = Rect::from_center(pos, width, height);
-        let texture = texture_manager.get_image_from_url(&self.tile_info.img_url);
-        if let Some(texture) = texture {
-            canvas.copy(&texture, None, rect).unwrap();
-        } else {
-            canvas.set_draw_color(TILE_COLOR);
-            canvas.fill_rect(rect).unwrap();
+        let priority = match highlight {
+            Highlight::Selected | Highlight::SelectedInPlace => Priority::Selected,
+            Highlight::None | Highlight::Framed => Priority::Visible,
+        };
+        let url = self.tile_info.image_url_for(aspect_ratio);
+        let broken = match texture_manager.get_image_from_url(url, priority) {
+            ImageState::Loaded(texture) => {
+                canvas.copy(&texture, None, rect).unwrap();
+                false
+            }
+            ImageState::Loading => {
+                canvas.set_draw_color(TILE_COLOR);
+                canvas.fill_rect(rect).unwrap();
+                false
+            }
+            ImageState::Retrying(_) | ImageState::Failed(_) => true,
+        };
+        if broken {
+            self.draw_broken(canvas, texture_manager, rect);
         }
     }
+
+    // Without an image, the title is the only way to tell what the tile is
+    fn draw_broken(
+        &self,
+        canvas: &mut Canvas<Window>,
+        texture_manager: &mut AsyncResourceManager,
+        rect: Rect,
+    ) {
+        canvas.set_draw_color(BROKEN_COLOR);
+        canvas.fill_rect(rect).unwrap();
+        canvas.set_draw_color(BROKEN_BORDER_COLOR);
+        canvas.draw_rect(rect).unwrap();
+
+        if self.tile_info.title.is_empty() {
+            return;
+        }
+        let (texture, (text_width, text_height)) =
+            texture_manager.get_text_texture_and_size(&self.tile_info.title);
+        // Long titles are shrunk to fit inside the tile
+        let max_width = rect.width().saturating_sub(2 * BROKEN_TITLE_MARGIN).max(1);
+        let max_height = rect.height().saturating_sub(2 * BROKEN_TITLE_MARGIN).max(1);
+        let scale = f32::min(
+            1.0,
+            f32::min(
+                max_width as f32 / text_width as f32,
+                max_height as f32 / text_height as f32,
+            ),
+        );
+        let text_rect = Rect::from_center(
+            rect.center(),
+            ((text_width as f32 * scale) as u32).max(1),
+            ((text_height as f32 * scale) as u32).max(1),
+        );
+        canvas.copy(&texture, None, text_rect).unwrap();
+    }
+}
+
+// Tiles that get a dot under a hero banner, at most max of them and always including current
+fn dot_range(count: usize, current: usize, max: usize) -> Range<usize> {
+    let shown = count.min(max);
+    let start = current.saturating_sub(shown / 2).min(count - shown);
+    start..start + shown
 }
 
 struct TileRow {
@@ -58,88 +176,298 @@ struct TileRow {
     window_size: usize,
     title: String,
     tiles: Vec<Tile>,
+    // Set while the row is a placeholder waiting for its set to be fetched
+    pending_ref: Option<String>,
+    // setId the next page is fetched with
+    set_id: Option<String>,
+    // Number of tiles in the whole set, including pages that haven't been fetched
+    total: usize,
+    style: RowStyle,
+    // Tiles are drawn at this aspect ratio, using the closest image the tile has
+    aspect_ratio: f32,
 }
 
 impl TileRow {
-    fn new_with_row_info(window_size: usize, row_info: RowInfo) -> Self {
+    fn new_with_row_info(window_size: usize, aspect_ratio: f32, row_info: RowInfo) -> Self {
         let mut tiles = Vec::new();
         for tile in row_info.tiles {
             tiles.push(Tile::new(tile));
         }
+        // Hero rows are a carousel of full width banners,
+        // brand tiles are smaller so one more fits on screen
+        let window_size = match row_info.style {
+            RowStyle::Hero => 1,
+            RowStyle::Brand => window_size + 1,
+            _ => window_size,
+        };
 
         Self {
             window_start: 0,
             window_size,
             title: row_info.title,
             tiles,
+            pending_ref: row_info.pending_ref,
+            set_id: row_info.set_id,
+            total: row_info.total,
+            style: row_info.style,
+            aspect_ratio,
+        }
+    }
+
+    // Appends the tiles of a page. For placeholder rows this is the first page.
+    fn append_page(&mut self, page: SetPage) {
+        self.tiles.extend(page.tiles.into_iter().map(Tile::new));
+        self.pending_ref = None;
+        self.set_id = page.set_id;
+        self.total = page.total.max(self.tiles.len());
+    }
+
+    // Stops fetching pages, e.g. after a page failed to load
+    fn stop_paging(&mut self) {
+        self.total = self.tiles.len();
+    }
+
+    fn has_more_pages(&self) -> bool {
+        self.set_id.is_some() && self.tiles.len() < self.total
+    }
+
+    // Offset of the next page, if the window is close enough to the end of the loaded tiles
+    fn next_page_offset(&self) -> Option<usize> {
+        let window_end = self.window_start + self.window_size;
+        if self.has_more_pages() && window_end + PAGE_LOAD_DISTANCE >= self.tiles.len() {
+            Some(self.tiles.len())
+        } else {
+            None
+        }
+    }
+
+    // Brand rows with every tile on screen show each tile once and don't scroll
+    fn fits_on_screen(&self) -> bool {
+        self.style == RowStyle::Brand
+            && !self.has_more_pages()
+            && self.tiles.len() <= self.window_size
+    }
+
+    // Number of tiles that can be selected
+    fn columns(&self) -> usize {
+        if self.pending_ref.is_none() && !self.tiles.is_empty() && self.fits_on_screen() {
+            self.tiles.len()
+        } else {
+            self.window_size
+        }
+    }
+
+    // Number of standard rows worth of vertical space the row takes up
+    fn slots(&self) -> usize {
+        match self.style {
+            RowStyle::Hero => HERO_SLOTS,
+            _ => 1,
         }
     }
 
     fn rotate(&mut self, right: bool) {
-        if self.tiles.is_empty() {
+        if self.tiles.is_empty() || self.fits_on_screen() {
             return;
         }
 
+        // Rows only wrap around once every page is loaded,
+        // until then they stop at the last loaded tile
         if right {
+            if self.has_more_pages() {
+                if self.window_start + self.window_size < self.tiles.len() {
+                    self.window_start += 1;
+                }
+                return;
+            }
             self.window_start += 1;
             if self.window_start >= self.tiles.len() {
                 self.window_start = 0;
             }
         // left
         } else if self.window_start == 0 {
-            self.window_start = self.tiles.len() - 1;
+            if !self.has_more_pages() {
+                self.window_start = self.tiles.len() - 1;
+            }
         } else {
             self.window_start -= 1;
         }
     }
 
+    // Empty tiles are shown until the set arrives, and for tiles on pages still being fetched
+    fn visible_tiles(&self) -> Vec<Option<&Tile>> {
+        if self.pending_ref.is_some() {
+            vec![None; self.window_size]
+        } else if self.has_more_pages() {
+            (self.window_start..self.window_start + self.window_size)
+                .map(|i| self.tiles.get(i))
+                .collect()
+        } else if self.fits_on_screen() {
+            self.tiles.iter().map(Some).collect()
+        } else {
+            self.tiles
+                .iter()
+                .cycle()
+                .skip(self.window_start)
+                .take(self.window_size)
+                .map(Some)
+                .collect()
+        }
+    }
+
+    // Tiles up to count places outside the window on either side, the ones scrolling would show next.
+    // Nearest first, alternating between the right and the left.
+    fn neighbour_tiles(&self, count: usize) -> Vec<&Tile> {
+        let len = self.tiles.len();
+        // Rows that don't scroll have nothing off screen
+        if self.pending_ref.is_some() || len <= self.window_size || self.fits_on_screen() {
+            return Vec::new();
+        }
+        let mut tiles = Vec::new();
+        for distance in 0..count {
+            let right = self.window_start + self.window_size + distance;
+            let left = self.window_start as isize - 1 - distance as isize;
+            if self.has_more_pages() {
+                // No wrapping around until every page is loaded
+                tiles.extend(self.tiles.get(right));
+                if left >= 0 {
+                    tiles.push(&self.tiles[left as usize]);
+                }
+            } else {
+                tiles.push(&self.tiles[right % len]);
+                tiles.push(&self.tiles[left.rem_euclid(len as isize) as usize]);
+            }
+        }
+        tiles
+    }
+
     fn draw(
         &self,
         canvas: &mut Canvas<Window>,
         texture_manager: &mut AsyncResourceManager,
-        left_x: i32,
-        center_y: i32,
-        element_width: u32,
-        element_height: u32,
+        // x is the left edge of the row, y is the vertical center of the tiles
+        pos: Point,
+        // Width of the whole row, and height of the space for its tiles
+        size: (u32, u32),
         padding: (u32, u32),
         // If this row is selected, the usize will be the relative position of the selected tile
-        // from [0, window_size)
+        // from [0, columns())
         selected: Option<usize>,
     ) {
+        let (left_x, center_y) = (pos.x(), pos.y());
+        let (row_width, element_height) = size;
         let (w_padding, h_padding) = padding;
-        // Display category title
-        let text_y = center_y - element_height as i32 / 2 - h_padding as i32 / 2;
-        let (texture, (text_width, text_height)) =
-            texture_manager.get_text_texture_and_size(&self.title);
-        let text_rect = Rect::from_center(
-            Point::new(left_x + text_width as i32 / 2, text_y),
-            text_width,
-            text_height,
-        );
-        canvas.copy(&texture, None, text_rect).unwrap();
-        drop(texture);
+        let element_width = row_width / self.window_size as u32 - w_padding;
+
+        // Hero and brand tiles speak for themselves, only other rows get a title
+        if !matches!(self.style, RowStyle::Hero | RowStyle::Brand) {
+            let text_y = center_y - element_height as i32 / 2 - h_padding as i32 / 2;
+            let (texture, (text_width, text_height)) =
+                texture_manager.get_text_texture_and_size(&self.title);
+            let text_rect = Rect::from_center(
+                Point::new(left_x + text_width as i32 / 2, text_y),
+                text_width,
+                text_height,
+            );
+            canvas.copy(&texture, None, text_rect).unwrap();
+            drop(texture);
+        }
 
-        if self.tiles.is_empty() {
+        let tile_y = center_y;
+        let mut tile_x = left_x + w_padding as i32 / 2 + element_width as i32 / 2;
+        let (tile_width, tile_height) =
+            fit_aspect_ratio((element_width, element_height), self.aspect_ratio);
+
+        let visible = self.visible_tiles();
+        if visible.is_empty() {
             return;
         }
 
-        let mut iter = self.tiles.iter().cycle();
-        for _ in 0..self.window_start {
-            iter.next();
+        for (i, tile) in visible.into_iter().enumerate() {
+            let tile_pos = Point::new(tile_x, tile_y);
+            let highlight = match (selected == Some(i), &self.style) {
+                (true, RowStyle::Hero) => Highlight::SelectedInPlace,
+                (true, _) => Highlight::Selected,
+                (false, RowStyle::Brand) => Highlight::Framed,
+                (false, _) => Highlight::None,
+            };
+            match tile {
+                Some(tile) => tile.draw(
+                    canvas,
+                    texture_manager,
+                    tile_pos,
+                    (tile_width, tile_height),
+                    self.aspect_ratio,
+                    highlight,
+                ),
+                None => {
+                    canvas.set_draw_color(TILE_COLOR);
+                    let rect = Rect::from_center(tile_pos, tile_width, tile_height);
+                    canvas.fill_rect(rect).unwrap();
+                }
+            }
+            tile_x += element_width as i32 + w_padding as i32;
         }
-        let tile_y = center_y;
 
-        let mut tile_x = left_x + w_padding as i32 / 2 + element_width as i32 / 2;
-        for (i, tile) in iter.take(self.window_size).enumerate() {
-            tile.draw(
-                canvas,
-                texture_manager,
-                Point::new(tile_x, tile_y),
-                element_width,
-                element_height,
-                selected.is_some() && (i == selected.unwrap()),
+        if self.style == RowStyle::Hero {
+            self.draw_carousel_dots(canvas, Point::new(left_x, center_y), size, tile_height);
+        }
+    }
+
+    // One dot per tile along the bottom of a hero banner, with the current one highlighted
+    fn draw_carousel_dots(
+        &self,
+        canvas: &mut Canvas<Window>,
+        pos: Point,
+        size: (u32, u32),
+        tile_height: u32,
+    ) {
+        let count = self.total.max(self.tiles.len());
+        if count < 2 {
+            return;
+        }
+        let dots = dot_range(count, self.window_start, MAX_DOTS);
+        let (row_width, _) = size;
+        // Squeezed together if the row is too narrow for the usual spacing
+        let spacing = (DOT_SIZE as i32 * 2).min(row_width as i32 / dots.len() as i32);
+        let first_x = pos.x() + row_width as i32 / 2 - spacing * (dots.len() as i32 - 1) / 2;
+        let y = pos.y() + tile_height as i32 / 2 - DOT_SIZE as i32 * 2;
+        for (slot, i) in dots.enumerate() {
+            let color = if i == self.window_start {
+                Color::WHITE
+            } else {
+                FRAME_COLOR
+            };
+            canvas.set_draw_color(color);
+            let dot = Rect::from_center(
+                Point::new(first_x + spacing * slot as i32, y),
+                DOT_SIZE,
+                DOT_SIZE,
             );
-            tile_x += element_width as i32 + w_padding as i32;
+            canvas.fill_rect(dot).unwrap();
+        }
+    }
+}
+
+/// Aspect ratio to draw the rows of a style at, written as `style=ratio`, e.g. `poster=0.71`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleAspectRatio {
+    pub style: String,
+    pub aspect_ratio: f32,
+}
+
+impl FromStr for StyleAspectRatio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (style, ratio) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected style=ratio, got {}", s))?;
+        match ratio.parse::<f32>() {
+            Ok(aspect_ratio) if aspect_ratio > 0.0 && aspect_ratio.is_finite() => Ok(Self {
+                style: style.to_string(),
+                aspect_ratio,
+            }),
+            _ => Err(format!("{} is not a positive aspect ratio", ratio)),
         }
     }
 }
@@ -152,11 +480,26 @@ pub enum Direction {
     Right,
 }
 
+// Whether the rows of the main json have arrived yet
+enum LoadStatus {
+    Loading,
+    Ready,
+    Failed(String),
+}
+
 pub struct TiledLayout {
+    status: LoadStatus,
     row_col: (usize, usize),
     window_start: usize,
     vert_window_size: usize,
     hori_window_size: usize,
+    // Placeholder rows are fetched once they're this many rows or less from window_start
+    load_distance: usize,
+    prefetch_tiles: usize,
+    prefetch_rows: usize,
+    prefetch_budget: usize,
+    // Aspect ratio to draw tiles at, keyed by row style
+    style_aspect_ratios: HashMap<String, f32>,
     left_x: i32,
     upper_y: i32,
     tile_rows: Vec<TileRow>,
@@ -164,21 +507,208 @@ pub struct TiledLayout {
 
 impl TiledLayout {
     pub fn new_with_row_infos(row_infos: Vec<RowInfo>) -> Self {
-        let hori_window_size = 4;
-        let mut tile_rows = Vec::new();
-        for info in row_infos {
-            tile_rows.push(TileRow::new_with_row_info(hori_window_size, info));
-        }
+        let mut style_aspect_ratios = HashMap::new();
+        style_aspect_ratios.insert("hero".to_string(), HERO_ASPECT_RATIO);
+        style_aspect_ratios.insert("poster".to_string(), POSTER_ASPECT_RATIO);
+        style_aspect_ratios.insert("character".to_string(), CHARACTER_ASPECT_RATIO);
 
-        Self {
+        let mut layout = Self {
+            status: LoadStatus::Ready,
             row_col: (0, 0),
             window_start: 0,
             vert_window_size: 4,
-            hori_window_size,
+            hori_window_size: 4,
+            load_distance: DEFAULT_LOAD_DISTANCE,
+            prefetch_tiles: DEFAULT_PREFETCH_TILES,
+            prefetch_rows: DEFAULT_PREFETCH_ROWS,
+            prefetch_budget: DEFAULT_PREFETCH_BUDGET,
+            style_aspect_ratios,
             left_x: 0,
             upper_y: 0,
-            tile_rows,
+            tile_rows: Vec::new(),
+        };
+        layout.set_rows(row_infos);
+        layout
+    }
+
+    fn set_rows(&mut self, row_infos: Vec<RowInfo>) {
+        self.tile_rows = Vec::new();
+        for info in row_infos {
+            let aspect_ratio = self.aspect_ratio_for(&info.style);
+            self.tile_rows.push(TileRow::new_with_row_info(
+                self.hori_window_size,
+                aspect_ratio,
+                info,
+            ));
+        }
+    }
+
+    fn aspect_ratio_for(&self, style: &RowStyle) -> f32 {
+        self.style_aspect_ratios
+            .get(style.name())
+            .copied()
+            .unwrap_or(DEFAULT_ASPECT_RATIO)
+    }
+
+    /// Draws rows with the given style using the image closest to its aspect ratio,
+    /// e.g. 0.71 for posters
+    pub fn set_style_aspect_ratio(&mut self, style_ratio: &StyleAspectRatio) {
+        self.style_aspect_ratios
+            .insert(style_ratio.style.clone(), style_ratio.aspect_ratio);
+        for i in 0..self.tile_rows.len() {
+            self.tile_rows[i].aspect_ratio = self.aspect_ratio_for(&self.tile_rows[i].style);
+        }
+    }
+
+    /// Creates an empty layout that shows a loading message until
+    /// update_rows receives the main rows from the row loader
+    pub fn new_loading() -> Self {
+        let mut layout = Self::new_with_row_infos(Vec::new());
+        layout.status = LoadStatus::Loading;
+        layout
+    }
+
+    pub fn set_load_distance(&mut self, load_distance: usize) {
+        self.load_distance = load_distance;
+    }
+
+    /// Requests images ahead of time for `tiles` tiles on either side of each visible row
+    /// and for the `rows` rows above and below the screen, up to `budget` images per frame.
+    /// 0 turns any of them off.
+    pub fn set_prefetch(&mut self, tiles: usize, rows: usize, budget: usize) {
+        self.prefetch_tiles = tiles;
+        self.prefetch_rows = rows;
+        self.prefetch_budget = budget;
+    }
+
+    // Distance from window_start, in either direction since rows wrap around
+    fn distance_from_window(&self, idx: usize) -> usize {
+        let len = self.tile_rows.len();
+        let ahead = (idx + len - self.window_start) % len;
+        let behind = (self.window_start + len - idx) % len;
+        ahead.min(behind)
+    }
+
+    /// Fills in placeholder rows whose sets have arrived and appends newly fetched pages.
+    /// Requests the sets for placeholder rows that are close to the window,
+    /// and the next page for rows scrolled close to the end of their loaded tiles.
+    /// Meant to be called once per frame.
+    pub fn update_rows(&mut self, row_loader: &mut RowLoader) {
+        match row_loader.process_main() {
+            Some(Ok(row_infos)) => {
+                self.set_rows(row_infos);
+                self.status = LoadStatus::Ready;
+            }
+            Some(Err(e)) => {
+                self.status = LoadStatus::Failed(format!("Could not load catalog: {}", e))
+            }
+            None => (),
+        }
+
+        for (query, resp) in row_loader.process_pending() {
+            match query {
+                SetQuery::Ref(ref_id) => self.finish_row(&ref_id, resp),
+                SetQuery::Page { set_id, offset } => self.finish_page(&set_id, offset, resp),
+            }
+        }
+
+        for (i, row) in self.tile_rows.iter().enumerate() {
+            if self.distance_from_window(i) > self.load_distance {
+                continue;
+            }
+            if let Some(ref_id) = &row.pending_ref {
+                row_loader.request(ref_id);
+            } else if let (Some(set_id), Some(offset)) = (&row.set_id, row.next_page_offset()) {
+                row_loader.request_page(set_id, offset);
+            }
+        }
+    }
+
+    fn finish_row(&mut self, ref_id: &str, resp: RowResponse) {
+        let idx = self
+            .tile_rows
+            .iter()
+            .position(|row| row.pending_ref.as_deref() == Some(ref_id));
+        let idx = match idx {
+            Some(idx) => idx,
+            None => return,
+        };
+        match resp {
+            Ok(page) if !page.tiles.is_empty() => self.tile_rows[idx].append_page(page),
+            // Same as when parsing upfront, rows without tiles are dropped
+            Ok(_) => {
+                println!("Could not parse tiles from set {}", ref_id);
+                self.remove_row(idx);
+            }
+            Err(e) => {
+                println!("Could not load set {}: {}", ref_id, e);
+                self.remove_row(idx);
+            }
+        }
+    }
+
+    // Pages are appended after the loaded tiles, so the selection and window stay put
+    fn finish_page(&mut self, set_id: &str, offset: usize, resp: RowResponse) {
+        let row = self
+            .tile_rows
+            .iter_mut()
+            .find(|row| row.set_id.as_deref() == Some(set_id) && row.tiles.len() == offset);
+        let row = match row {
+            Some(row) => row,
+            None => return,
+        };
+        match resp {
+            Ok(page) if !page.ends_set_early() => row.append_page(page),
+            Ok(_) => {
+                println!("Set {} ended before its last page", set_id);
+                row.stop_paging();
+            }
+            Err(e) => {
+                println!("Could not load page {} of set {}: {}", offset, set_id, e);
+                row.stop_paging();
+            }
+        }
+    }
+
+    // Keeps the rows on screen in place when a row is removed
+    fn remove_row(&mut self, idx: usize) {
+        self.tile_rows.remove(idx);
+        if idx < self.window_start {
+            self.window_start -= 1;
+        }
+        if self.window_start >= self.tile_rows.len() {
+            self.window_start = 0;
+        }
+        self.row_col.0 = self.row_col.0.min(self.visible_rows() - 1);
+    }
+
+    // Number of rows on screen starting from window_start.
+    // Usually vert_window_size, but hero rows take up the space of more than one row.
+    fn visible_rows(&self) -> usize {
+        let mut slots = 0;
+        let mut count = 0;
+        let iter = self.tile_rows.iter().cycle().skip(self.window_start);
+        for row in iter.take(self.vert_window_size) {
+            slots += row.slots();
+            if slots > self.vert_window_size {
+                break;
+            }
+            count += 1;
         }
+        // Always show at least one row, even if it doesn't fit
+        count.max(1)
+    }
+
+    fn selected_row(&self) -> usize {
+        // row_col.0 is relative to window_start
+        // so row_col.0 + window_start gives us the selected row
+        // mod total number of rows in case we wrap around
+        (self.window_start + self.row_col.0) % self.tile_rows.len()
+    }
+
+    // Column of the selected tile. Hero and brand rows can have fewer columns than row_col.1
+    fn selected_column(&self, row: usize) -> usize {
+        self.row_col.1.min(self.tile_rows[row].columns() - 1)
     }
 
     fn rotate(&mut self, down: bool) {
@@ -196,21 +726,23 @@ impl TiledLayout {
     }
 
     pub fn handle_direction(&mut self, direction: Direction) {
+        if self.tile_rows.is_empty() {
+            return;
+        }
         match direction {
             Direction::Left => {
+                let idx = self.selected_row();
+                self.row_col.1 = self.selected_column(idx);
                 if self.row_col.1 == 0 {
-                    // row_col.0 is relative to window_start
-                    // so row_col.0 + window_start gives us the selected row
-                    // mod total number of rows in case we wrap around
-                    let idx = (self.window_start + self.row_col.0) % self.tile_rows.len();
                     self.tile_rows[idx].rotate(false);
                 } else {
                     self.row_col.1 -= 1;
                 }
             }
             Direction::Right => {
-                if self.row_col.1 == self.hori_window_size - 1 {
-                    let idx = (self.window_start + self.row_col.0) % self.tile_rows.len();
+                let idx = self.selected_row();
+                self.row_col.1 = self.selected_column(idx);
+                if self.row_col.1 == self.tile_rows[idx].columns() - 1 {
                     self.tile_rows[idx].rotate(true);
                 } else {
                     self.row_col.1 += 1;
@@ -224,10 +756,21 @@ impl TiledLayout {
                 }
             }
             Direction::Down => {
-                if self.row_col.0 == self.vert_window_size - 1 {
-                    self.rotate(true);
-                } else {
+                if self.row_col.0 + 1 < self.visible_rows() {
                     self.row_col.0 += 1;
+                } else {
+                    // Scroll until the next row fits on screen, which can take more than
+                    // one step when a hero row comes into view
+                    let len = self.tile_rows.len();
+                    let next = (self.selected_row() + 1) % len;
+                    loop {
+                        self.rotate(true);
+                        let offset = (next + len - self.window_start) % len;
+                        if offset < self.visible_rows() {
+                            self.row_col.0 = offset;
+                            break;
+                        }
+                    }
                 }
             }
         }
@@ -240,6 +783,25 @@ impl TiledLayout {
         width: u32,
         height: u32,
     ) {
+        let message = match &self.status {
+            LoadStatus::Loading => Some("Loading..."),
+            LoadStatus::Failed(message) => Some(message.as_str()),
+            LoadStatus::Ready => None,
+        };
+        if let Some(message) = message {
+            let (texture, (text_width, text_height)) =
+                texture_manager.get_text_texture_and_size(message);
+            let center = Point::new(width as i32 / 2, height as i32 / 2);
+            canvas
+                .copy(
+                    &texture,
+                    None,
+                    Rect::from_center(center, text_width, text_height),
+                )
+                .unwrap();
+            return;
+        }
+
         if self.tile_rows.is_empty() {
             return;
         }
@@ -250,24 +812,223 @@ impl TiledLayout {
         let (w_padding, h_padding) = (width / 20, height / 20);
         let element_height = height / self.vert_window_size as u32 - h_padding;
         let element_width = width / self.hori_window_size as u32 - w_padding;
-        let mut center_y = self.upper_y + h_padding as i32 + element_height as i32 / 2;
-        for (i, tilerow) in iter.take(self.vert_window_size).enumerate() {
+        let row_width = (element_width + w_padding) * self.hori_window_size as u32;
+        let mut top_y = self.upper_y + h_padding as i32;
+        for (i, tilerow) in iter.take(self.visible_rows()).enumerate() {
             let selected = if i == self.row_col.0 {
-                Some(self.row_col.1)
+                Some(self.row_col.1.min(tilerow.columns() - 1))
             } else {
                 None
             };
+            // Rows taking up several slots also get the padding between them
+            let slots = tilerow.slots() as u32;
+            let row_height = element_height * slots + h_padding * (slots - 1);
             tilerow.draw(
                 canvas,
                 texture_manager,
-                self.left_x,
-                center_y,
-                element_width as u32,
-                element_height as u32,
+                Point::new(self.left_x, top_y + row_height as i32 / 2),
+                (row_width, row_height),
                 (w_padding, h_padding),
                 selected,
             );
-            center_y += element_height as i32 + h_padding as i32;
+            top_y += row_height as i32 + h_padding as i32;
+        }
+
+        for url in self.prefetch_urls() {
+            texture_manager.prefetch_image(url);
+        }
+    }
+
+    // Images of the tiles just off screen, closest first, within the prefetch budget.
+    // First the neighbours of the visible rows, starting with the selected row,
+    // then the rows below and above the screen.
+    fn prefetch_urls(&self) -> Vec<&str> {
+        let len = self.tile_rows.len();
+        if len == 0 || self.prefetch_budget == 0 {
+            return Vec::new();
+        }
+        let visible_rows = self.visible_rows();
+        let mut on_screen: Vec<usize> = (0..visible_rows)
+            .map(|i| (self.window_start + i) % len)
+            .collect();
+        let selected = on_screen.remove(self.row_col.0.min(visible_rows - 1));
+        on_screen.insert(0, selected);
+
+        let mut tiles: Vec<(&Tile, f32)> = Vec::new();
+        let neighbours: Vec<(Vec<&Tile>, f32)> = on_screen
+            .iter()
+            .map(|&i| {
+                let row = &self.tile_rows[i];
+                (row.neighbour_tiles(self.prefetch_tiles), row.aspect_ratio)
+            })
+            .collect();
+        for distance in 0..self.prefetch_tiles * 2 {
+            for (row_tiles, aspect_ratio) in &neighbours {
+                if let Some(tile) = row_tiles.get(distance) {
+                    tiles.push((tile, *aspect_ratio));
+                }
+            }
+        }
+
+        for distance in 0..self.prefetch_rows {
+            let below = (self.window_start + visible_rows + distance) % len;
+            let above = (self.window_start as isize - 1 - distance as isize)
+                .rem_euclid(len as isize) as usize;
+            for i in [below, above] {
+                // Short layouts wrap around onto the screen
+                if on_screen.contains(&i) {
+                    continue;
+                }
+                let row = &self.tile_rows[i];
+                tiles.extend(
+                    row.visible_tiles()
+                        .into_iter()
+                        .flatten()
+                        .map(|tile| (tile, row.aspect_ratio)),
+                );
+            }
+        }
+
+        let mut seen = HashSet::new();
+        tiles
+            .into_iter()
+            .map(|(tile, aspect_ratio)| tile.tile_info.image_url_for(aspect_ratio))
+            .filter(|url| !url.is_empty() && seen.insert(*url))
+            .take(self.prefetch_budget)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn row(style: RowStyle, tiles: usize) -> RowInfo {
+        RowInfo {
+            title: style.name().to_string(),
+            style,
+            tiles: (0..tiles).map(|_| TileInfo::default()).collect(),
+            pending_ref: None,
+            set_id: None,
+            total: tiles,
+        }
+    }
+
+    // Each tile's image is named after the row's title and the tile's position
+    fn row_with_images(title: &str, tiles: usize) -> RowInfo {
+        RowInfo {
+            title: title.to_string(),
+            tiles: (0..tiles)
+                .map(|i| TileInfo {
+                    img_url: format!("{}/{}", title, i),
+                    ..TileInfo::default()
+                })
+                .collect(),
+            total: tiles,
+            ..row(RowStyle::Standard, 0)
+        }
+    }
+
+    #[test]
+    fn test_prefetch_neighbours() {
+        let mut layout = TiledLayout::new_with_row_infos(vec![
+            row_with_images("a", 8),
+            row_with_images("b", 8),
+            row_with_images("c", 8),
+        ]);
+        // Every row is on screen, so there are no rows to prefetch
+        layout.set_prefetch(1, 1, 32);
+        // Closest on the right first, then the left, which wraps around
+        assert_eq!(
+            layout.prefetch_urls(),
+            ["a/4", "b/4", "c/4", "a/7", "b/7", "c/7"]
+        );
+    }
+
+    #[test]
+    fn test_prefetch_rows_and_budget() {
+        let mut layout = TiledLayout::new_with_row_infos(
+            ["a", "b", "c", "d", "e", "f"]
+                .iter()
+                .map(|title| row_with_images(title, 2))
+                .collect(),
+        );
+        // Rows of 2 tiles fit on screen, so only the rows below and above are prefetched
+        layout.set_prefetch(2, 1, 32);
+        assert_eq!(layout.prefetch_urls(), ["e/0", "e/1", "f/0", "f/1"]);
+        layout.set_prefetch(2, 1, 3);
+        assert_eq!(layout.prefetch_urls(), ["e/0", "e/1", "f/0"]);
+        layout.set_prefetch(2, 1, 0);
+        assert!(layout.prefetch_urls().is_empty());
+    }
+
+    #[test]
+    fn test_style_aspect_ratio() {
+        let mut layout = TiledLayout::new_with_row_infos(vec![
+            row(RowStyle::Standard, 2),
+            row(RowStyle::Hero, 2),
+        ]);
+        assert_eq!(layout.tile_rows[1].aspect_ratio, HERO_ASPECT_RATIO);
+        layout.set_style_aspect_ratio(&"hero=2.5".parse().unwrap());
+        layout.set_style_aspect_ratio(&"standard=0.71".parse().unwrap());
+        assert_eq!(layout.tile_rows[0].aspect_ratio, 0.71);
+        assert_eq!(layout.tile_rows[1].aspect_ratio, 2.5);
+        for bad in ["hero", "hero=wide", "hero=0", "hero=-1"] {
+            assert!(bad.parse::<StyleAspectRatio>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_dot_range() {
+        assert_eq!(dot_range(3, 1, MAX_DOTS), 0..3);
+        // Long carousels follow the current tile, without running past either end
+        assert_eq!(dot_range(40, 0, 15), 0..15);
+        assert_eq!(dot_range(40, 20, 15), 13..28);
+        assert_eq!(dot_range(40, 39, 15), 25..40);
+    }
+
+    #[test]
+    fn test_hero_takes_two_rows() {
+        let mut layout = TiledLayout::new_with_row_infos(vec![
+            row(RowStyle::Standard, 8),
+            row(RowStyle::Standard, 8),
+            row(RowStyle::Standard, 8),
+            row(RowStyle::Hero, 3),
+            row(RowStyle::Standard, 8),
+        ]);
+        // The hero row doesn't fit below the first three
+        assert_eq!(layout.visible_rows(), 3);
+        layout.handle_direction(Direction::Down);
+        layout.handle_direction(Direction::Down);
+        layout.handle_direction(Direction::Down);
+        assert_eq!(layout.selected_row(), 3);
+        assert_eq!(layout.window_start, 1);
+        assert_eq!(layout.visible_rows(), 3);
+    }
+
+    #[test]
+    fn test_carousel_and_brand_columns() {
+        let mut layout = TiledLayout::new_with_row_infos(vec![
+            row(RowStyle::Standard, 8),
+            row(RowStyle::Hero, 3),
+            row(RowStyle::Brand, 3),
+        ]);
+        layout.handle_direction(Direction::Right);
+        layout.handle_direction(Direction::Right);
+        assert_eq!(layout.row_col.1, 2);
+
+        // Hero rows have a single column, so right moves the carousel
+        layout.handle_direction(Direction::Down);
+        layout.handle_direction(Direction::Right);
+        assert_eq!(layout.row_col.1, 0);
+        assert_eq!(layout.tile_rows[1].window_start, 1);
+
+        // Brand rows that fit on screen don't scroll
+        layout.handle_direction(Direction::Down);
+        for _ in 0..4 {
+            layout.handle_direction(Direction::Right);
         }
+        assert_eq!(layout.row_col.1, 2);
+        assert_eq!(layout.tile_rows[2].window_start, 0);
     }
 }
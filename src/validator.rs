@@ -0,0 +1,333 @@
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::json_model::{Container, ContainerSet, CuratedSet};
+use crate::json_parser::{JsonParser, ParseError};
+
+// Lints a catalog so content teams can catch broken rows before publishing.
+// The parser skips anything it can't show, this instead walks every container
+// and reports what would have been skipped, and why.
+
+/// Everything found wrong with a single container
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// The container doesn't match the model, so the row is skipped.
+    /// The error includes the path of the failing field.
+    Unparseable { error: String },
+    /// The set's type is not one the parser understands, so the row is skipped
+    UnknownSetType { type_name: String },
+    /// The set behind a SetRef could not be fetched or parsed
+    UnreachableRef { ref_id: String, error: String },
+    /// A later page of the set could not be fetched or parsed
+    UnreachablePage {
+        set_id: String,
+        offset: usize,
+        error: String,
+    },
+    /// The set has no items, so the row is skipped
+    NoItems,
+    /// Fewer items than meta.hits promised
+    MissingItems { expected: usize, found: usize },
+    /// Item is shown with an empty title
+    MissingTitle {
+        item: usize,
+        content_id: Option<String>,
+    },
+    /// Item has no image at any aspect ratio, so it's shown as an empty tile
+    MissingImage {
+        item: usize,
+        content_id: Option<String>,
+        title: String,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unparseable { error } => write!(f, "could not parse container: {}", error),
+            Problem::UnknownSetType { type_name } => write!(f, "unknown set type {}", type_name),
+            Problem::UnreachableRef { ref_id, error } => {
+                write!(f, "could not load set {}: {}", ref_id, error)
+            }
+            Problem::UnreachablePage {
+                set_id,
+                offset,
+                error,
+            } => write!(
+                f,
+                "could not load page {} of set {}: {}",
+                offset, set_id, error
+            ),
+            Problem::NoItems => write!(f, "no items"),
+            Problem::MissingItems { expected, found } => {
+                write!(f, "expected {} items, found {}", expected, found)
+            }
+            Problem::MissingTitle { item, content_id } => write!(
+                f,
+                "item {} ({}) has no title",
+                item,
+                content_id.as_deref().unwrap_or("no contentId")
+            ),
+            Problem::MissingImage {
+                item,
+                content_id,
+                title,
+            } => write!(
+                f,
+                "item {} \"{}\" ({}) has no image",
+                item,
+                title,
+                content_id.as_deref().unwrap_or("no contentId")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContainerReport {
+    pub index: usize,
+    pub title: String,
+    /// Type of the set, for a SetRef the type of the set it resolved to, e.g. "SetRef -> TrendingSet"
+    pub set_type: String,
+    pub ref_id: Option<String>,
+    /// Items found across every page
+    pub items: usize,
+    /// Total items according to the set's paging info
+    pub hits: Option<usize>,
+    pub problems: Vec<Problem>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    /// Where the home json was read from
+    pub location: String,
+    pub containers: Vec<ContainerReport>,
+}
+
+impl ValidationReport {
+    pub fn problem_count(&self) -> usize {
+        self.containers
+            .iter()
+            .map(|container| container.problems.len())
+            .sum()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problem_count() == 0
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.location)?;
+        for container in &self.containers {
+            write!(
+                f,
+                "[{}] {} \"{}\": {} items",
+                container.index, container.set_type, container.title, container.items
+            )?;
+            if let Some(hits) = container.hits {
+                write!(f, " of {}", hits)?;
+            }
+            if let Some(ref_id) = &container.ref_id {
+                write!(f, " (refId {})", ref_id)?;
+            }
+            writeln!(f)?;
+            for problem in &container.problems {
+                writeln!(f, "    error: {}", problem)?;
+            }
+        }
+        write!(
+            f,
+            "{} containers, {} problems",
+            self.containers.len(),
+            self.problem_count()
+        )
+    }
+}
+
+// The set's "type" straight from the json, for containers the model can't describe
+fn raw_set_type(json: &Value) -> Option<&str> {
+    json.pointer("/set/type").and_then(Value::as_str)
+}
+
+/// Walks every container of the parser's catalog, fetching referenced sets and every page.
+/// Only fails if the home json itself can't be loaded, everything else ends up in the report.
+pub async fn validate(parser: &JsonParser) -> Result<ValidationReport, ParseError> {
+    let mut containers = Vec::new();
+    // Containers are decoded one at a time, so a broken one is reported along with the rest
+    for (index, json) in parser.parse_raw_containers().await?.into_iter().enumerate() {
+        let set_type = raw_set_type(&json).unwrap_or("unknown").to_string();
+        let container: Container = match serde_path_to_error::deserialize(json) {
+            Ok(container) => container,
+            Err(error) => {
+                containers.push(ContainerReport {
+                    index,
+                    title: String::new(),
+                    set_type,
+                    ref_id: None,
+                    items: 0,
+                    hits: None,
+                    problems: vec![Problem::Unparseable {
+                        error: error.to_string(),
+                    }],
+                });
+                continue;
+            }
+        };
+        let mut report = ContainerReport {
+            index,
+            title: parser.get_container_title(&container),
+            set_type: container.set.type_name().to_string(),
+            ref_id: None,
+            items: 0,
+            hits: None,
+            problems: Vec::new(),
+        };
+        match &container.set {
+            ContainerSet::CuratedSet(set)
+            | ContainerSet::TrendingSet(set)
+            | ContainerSet::PersonalizedCuratedSet(set) => {
+                check_set(parser, set, set.set_id.as_deref(), &mut report).await
+            }
+            ContainerSet::SetRef(set_ref) => {
+                report.ref_id = Some(set_ref.ref_id.clone());
+                match parser.parse_ref_set(&set_ref.ref_id).await {
+                    Ok(ref_set) => {
+                        report.set_type = format!("SetRef -> {}", ref_set.type_name());
+                        let set_id = ref_set.page_id(&set_ref.ref_id).to_string();
+                        let set = ref_set.into_set();
+                        check_set(parser, &set, Some(&set_id), &mut report).await;
+                    }
+                    Err(e) => report.problems.push(Problem::UnreachableRef {
+                        ref_id: set_ref.ref_id.clone(),
+                        error: e.to_string(),
+                    }),
+                }
+            }
+            // The model throws the type away, so it's taken from the json
+            ContainerSet::Unknown => {
+                report.set_type = set_type.clone();
+                report.problems.push(Problem::UnknownSetType {
+                    type_name: set_type,
+                });
+            }
+        }
+        containers.push(report);
+    }
+
+    Ok(ValidationReport {
+        location: parser.source().home_location(),
+        containers,
+    })
+}
+
+// Checks the items of the first page, then fetches and checks every later page
+async fn check_set(
+    parser: &JsonParser,
+    first_page: &CuratedSet,
+    set_id: Option<&str>,
+    report: &mut ContainerReport,
+) {
+    report.hits = first_page.meta.as_ref().map(|meta| meta.hits);
+    check_items(parser, first_page, report);
+
+    if let (Some(set_id), Some(hits)) = (set_id, report.hits) {
+        while report.items < hits {
+            match parser.parse_page_json(set_id, report.items).await {
+                Ok(page) if !page.items.is_empty() => check_items(parser, &page, report),
+                Ok(_) => break,
+                Err(e) => {
+                    report.problems.push(Problem::UnreachablePage {
+                        set_id: set_id.to_string(),
+                        offset: report.items,
+                        error: e.to_string(),
+                    });
+                    // The missing items are already covered by this problem
+                    return;
+                }
+            }
+        }
+    }
+
+    if report.items == 0 {
+        report.problems.push(Problem::NoItems);
+    } else if let Some(hits) = report.hits {
+        if report.items < hits {
+            report.problems.push(Problem::MissingItems {
+                expected: hits,
+                found: report.items,
+            });
+        }
+    }
+}
+
+fn check_items(parser: &JsonParser, set: &CuratedSet, report: &mut ContainerReport) {
+    for item in &set.items {
+        let index = report.items;
+        report.items += 1;
+        let tile = parser.get_tile_info(item);
+        if tile.title.is_empty() {
+            report.problems.push(Problem::MissingTitle {
+                item: index,
+                content_id: tile.content_id.clone(),
+            });
+        }
+        if tile.img_url.is_empty() && tile.images.is_empty() {
+            report.problems.push(Problem::MissingImage {
+                item: index,
+                content_id: tile.content_id,
+                title: tile.title,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::json_parser::DocumentSource;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_validate_fixture() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/snapshot");
+        let parser = JsonParser::with_source(DocumentSource::Directory(dir));
+        let report = validate(&parser).await.unwrap();
+
+        assert_eq!(report.containers.len(), 3);
+        assert_eq!(report.containers[0].set_type, "CuratedSet");
+        assert_eq!(report.containers[0].items, 2);
+        assert_eq!(report.containers[1].set_type, "SetRef -> TrendingSet");
+        // Includes the item on the second page
+        assert_eq!(report.containers[1].items, 3);
+        assert!(report.containers[1].problems.is_empty());
+        // The fixture's empty row is the only problem
+        assert_eq!(report.containers[2].problems, [Problem::NoItems]);
+        assert!(!report.is_ok());
+
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/validate");
+        let parser = JsonParser::with_source(DocumentSource::Directory(dir));
+        let report = validate(&parser).await.unwrap();
+
+        // The broken container doesn't stop the others from being checked
+        assert_eq!(report.containers.len(), 3);
+        assert_eq!(report.containers[0].title, "New to Disney+");
+        assert!(report.containers[0].problems.is_empty());
+        assert_eq!(report.containers[1].set_type, "CuratedSet");
+        match &report.containers[1].problems[..] {
+            [Problem::Unparseable { error }] => assert!(error.contains("text"), "{}", error),
+            problems => panic!("Expected an unparseable container, got {:?}", problems),
+        }
+        assert_eq!(report.containers[2].set_type, "BecauseYouSet");
+        assert_eq!(
+            report.containers[2].problems,
+            [Problem::UnknownSetType {
+                type_name: "BecauseYouSet".to_string()
+            }]
+        );
+    }
+}
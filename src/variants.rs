@@ -0,0 +1,208 @@
+use std::{collections::HashMap, fmt};
+
+// Item text and images come in several variants keyed by the entity they came from,
+// e.g. `{"series": {...}, "program": {...}}`. Which one is shown is decided by an explicit
+// precedence list, and anything surprising about the choice is recorded as a VariantIssue
+// so it can be reported instead of silently showing an empty title or the wrong image.
+
+/// Order in which variants are tried, first present variant wins.
+#[derive(Debug, Clone)]
+pub struct VariantPrecedence {
+    pub text: Vec<String>,
+    pub image: Vec<String>,
+}
+
+impl Default for VariantPrecedence {
+    // Matches what the parser always did, before the order was configurable
+    fn default() -> Self {
+        Self {
+            text: vec!["collection".into(), "program".into(), "series".into()],
+            image: vec![
+                "default".into(),
+                "collection".into(),
+                "program".into(),
+                "series".into(),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariantField {
+    Title,
+    /// Image for the given aspect ratio, e.g. "1.78"
+    Image(String),
+    BriefDescription,
+    FullDescription,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariantIssue {
+    /// Several variants had different values. The chosen one won by precedence.
+    Conflict {
+        field: VariantField,
+        chosen: String,
+        ignored: Vec<String>,
+    },
+    /// None of the variants in the precedence list had a value, so the field is empty.
+    Missing {
+        field: VariantField,
+        available: Vec<String>,
+    },
+}
+
+impl fmt::Display for VariantField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariantField::Title => write!(f, "title"),
+            VariantField::Image(ratio) => write!(f, "{} image", ratio),
+            VariantField::BriefDescription => write!(f, "brief description"),
+            VariantField::FullDescription => write!(f, "full description"),
+        }
+    }
+}
+
+impl fmt::Display for VariantIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariantIssue::Conflict {
+                field,
+                chosen,
+                ignored,
+            } => write!(
+                f,
+                "{} variants disagree, used {} over {}",
+                field,
+                chosen,
+                ignored.join(", ")
+            ),
+            VariantIssue::Missing { field, available } if available.is_empty() => {
+                write!(f, "no {}, left empty", field)
+            }
+            VariantIssue::Missing { field, available } => write!(
+                f,
+                "no {} in a known variant, left empty (found {})",
+                field,
+                available.join(", ")
+            ),
+        }
+    }
+}
+
+/// Picks the value of the first variant in order that has one.
+/// Records a Conflict if lower precedence variants disagree with it, and a Missing
+/// if report_missing is set and no variant had a value.
+pub fn resolve<'a, T, V: PartialEq>(
+    variants: &'a HashMap<String, T>,
+    order: &[String],
+    value: impl Fn(&'a T) -> Option<V>,
+    field: VariantField,
+    report_missing: bool,
+    issues: &mut Vec<VariantIssue>,
+) -> Option<V> {
+    let mut candidates = order
+        .iter()
+        .filter_map(|name| Some((name, value(variants.get(name)?)?)));
+
+    let (chosen, chosen_value) = match candidates.next() {
+        Some(candidate) => candidate,
+        None => {
+            if report_missing {
+                let mut available: Vec<String> = variants.keys().cloned().collect();
+                available.sort();
+                issues.push(VariantIssue::Missing { field, available });
+            }
+            return None;
+        }
+    };
+
+    let ignored: Vec<String> = candidates
+        .filter(|(_, value)| *value != chosen_value)
+        .map(|(name, _)| name.clone())
+        .collect();
+    if !ignored.is_empty() {
+        issues.push(VariantIssue::Conflict {
+            field,
+            chosen: chosen.clone(),
+            ignored,
+        });
+    }
+    Some(chosen_value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn variants(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn order(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_precedence_order() {
+        let variants = variants(&[("series", "A"), ("program", "B")]);
+        let mut issues = Vec::new();
+        let value = resolve(
+            &variants,
+            &order(&["program", "series"]),
+            |v| Some(v.clone()),
+            VariantField::Title,
+            true,
+            &mut issues,
+        );
+        assert_eq!(value.as_deref(), Some("B"));
+        assert_eq!(
+            issues,
+            [VariantIssue::Conflict {
+                field: VariantField::Title,
+                chosen: "program".into(),
+                ignored: vec!["series".into()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_matching_variants_are_not_a_conflict() {
+        let variants = variants(&[("series", "A"), ("program", "A")]);
+        let mut issues = Vec::new();
+        let value = resolve(
+            &variants,
+            &order(&["series", "program"]),
+            |v| Some(v.clone()),
+            VariantField::Title,
+            true,
+            &mut issues,
+        );
+        assert_eq!(value.as_deref(), Some("A"));
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn test_missing() {
+        let variants = variants(&[("episode", "A")]);
+        let mut issues = Vec::new();
+        let value = resolve(
+            &variants,
+            &order(&["series"]),
+            |v| Some(v.clone()),
+            VariantField::Title,
+            true,
+            &mut issues,
+        );
+        assert_eq!(value, None);
+        assert_eq!(
+            issues,
+            [VariantIssue::Missing {
+                field: VariantField::Title,
+                available: vec!["episode".into()],
+            }]
+        );
+    }
+}